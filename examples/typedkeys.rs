@@ -0,0 +1,48 @@
+use bevy::{log::LogPlugin, prelude::*};
+use bevy_pkv::{PkvKey, PkvStore};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct User {
+    name: String,
+}
+
+// The value type is part of the key, so e.g. `pkv.get_typed(USER_NAME)` can only return a `String`
+const USER_NAME: PkvKey<String> = PkvKey::new("username");
+const USER: PkvKey<User> = PkvKey::new("user");
+
+fn setup(mut pkv: ResMut<PkvStore>) {
+    // strings
+    if let Ok(username) = pkv.get_typed(USER_NAME) {
+        info!("Welcome back {username}");
+    } else {
+        info!("First time user, setting username to 'alice'");
+        pkv.set_typed(USER_NAME, &"alice".to_string())
+            .expect("failed to store username");
+    }
+
+    // serde types
+    if let Ok(user) = pkv.get_typed(USER) {
+        info!("Welcome back {}", user.name);
+    } else {
+        info!("First time user, setting user to 'bob'");
+        let user = User {
+            name: "bob".to_string(),
+        };
+        pkv.set_typed(USER, &user)
+            .expect("failed to store User struct");
+    }
+}
+
+fn main() {
+    // When building for WASM, print panics to the browser console
+    #[cfg(target_arch = "wasm32")]
+    console_error_panic_hook::set_once();
+
+    App::new()
+        .insert_resource(PkvStore::new("BevyPkv", "TypedKeysExample"))
+        .add_plugins(MinimalPlugins)
+        .add_plugins(LogPlugin::default())
+        .add_systems(Startup, setup)
+        .run();
+}
@@ -0,0 +1,245 @@
+//! Non-blocking versions of the basic operations, run on a dedicated I/O thread
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::{SendError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::envelope::Entry;
+use crate::{ChangeKind, GetError, PkvStore, RemoveError, SetError};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the async operations of a store one at a time, in the order they were started
+///
+/// On wasm, and if the thread can't be spawned, operations run immediately on the
+/// calling thread instead.
+#[derive(Debug, Default)]
+pub(crate) struct IoThread {
+    sender: Option<Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl IoThread {
+    #[cfg(wasm)]
+    pub(crate) fn start() -> Self {
+        Self::default()
+    }
+
+    #[cfg(not(wasm))]
+    pub(crate) fn start() -> Self {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Job>();
+        let run_jobs = move || {
+            for job in receiver {
+                // a panicking job fails its own future, but shouldn't take down the
+                // operations queued after it
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+        };
+        match std::thread::Builder::new()
+            .name("bevy_pkv io".to_string())
+            .spawn(run_jobs)
+        {
+            Ok(handle) => Self {
+                sender: Some(sender),
+                handle: Some(handle),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn run<T: Send + 'static>(&self, job: impl FnOnce() -> T + Send + 'static) -> PkvFuture<T> {
+        let (promise, future) = PkvFuture::pending();
+        let job: Job = Box::new(move || promise.complete(job()));
+        match &self.sender {
+            Some(sender) => {
+                if let Err(SendError(job)) = sender.send(job) {
+                    job();
+                }
+            }
+            None => job(),
+        }
+        future
+    }
+}
+
+impl Drop for IoThread {
+    /// Waits for the queued operations, so they're done before the store is reopened
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    value: Option<T>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+fn lock<T>(slot: &Mutex<Slot<T>>) -> MutexGuard<'_, Slot<T>> {
+    slot.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The result of an async operation on a [`PkvStore`]
+///
+/// The operation is started right away, and completes even if the future is dropped.
+/// The future doesn't depend on any particular async runtime, so it can be awaited
+/// in a bevy task, with tokio, or with any other executor.
+///
+/// # Panics
+///
+/// Polling panics if the backend panicked while running the operation.
+#[derive(Debug)]
+pub struct PkvFuture<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> PkvFuture<T> {
+    fn ready(value: T) -> Self {
+        Self(Arc::new(Mutex::new(Slot {
+            value: Some(value),
+            done: true,
+            waker: None,
+        })))
+    }
+
+    fn pending() -> (Promise<T>, Self) {
+        let slot = Arc::new(Mutex::new(Slot {
+            value: None,
+            done: false,
+            waker: None,
+        }));
+        (Promise(slot.clone()), Self(slot))
+    }
+}
+
+impl<T> Future for PkvFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = lock(&self.0);
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(value);
+        }
+        if slot.done {
+            panic!("PkvFuture polled after completion, or the operation panicked");
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// The sending half of a [`PkvFuture`], which completes it when dropped
+struct Promise<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Promise<T> {
+    fn complete(self, value: T) {
+        lock(&self.0).value = Some(value);
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut slot = lock(&self.0);
+            slot.done = true;
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Non-blocking versions of the basic operations, enabled by the `async` feature
+///
+/// The operations are run on a thread owned by the store, which is started on first
+/// use. They run one at a time, in the order they were started, so e.g. a
+/// [`PkvStore::get_async`] sees the value of an earlier [`PkvStore::set_async`] even
+/// if that future hasn't been awaited yet. The blocking methods don't wait for queued
+/// operations though. Dropping the store waits for all of them to finish.
+///
+/// On wasm, `localStorage` has no async API, so the operations complete immediately.
+impl PkvStore {
+    fn io(&self) -> &IoThread {
+        self.io.get_or_init(IoThread::start)
+    }
+
+    /// Get the value for the given key without blocking
+    ///
+    /// Like [`PkvStore::get`], but the value is read and deserialized on the I/O thread.
+    pub fn get_async<T: DeserializeOwned + Send + 'static>(
+        &self,
+        key: impl AsRef<str>,
+    ) -> PkvFuture<Result<T, GetError>> {
+        let backend = self.backend.clone();
+        let codec = self.codec.clone();
+        let key = key.as_ref().to_string();
+        self.io()
+            .run(move || crate::read_value(&*backend, &*codec, &key))
+    }
+
+    /// Serialize and store the value without blocking
+    ///
+    /// Like [`PkvStore::set`]. The value is serialized, and compressed if enabled,
+    /// right away, so serialization errors are returned by the future without
+    /// touching the I/O thread.
+    pub fn set_async<T: Serialize>(
+        &mut self,
+        key: impl AsRef<str>,
+        value: &T,
+    ) -> PkvFuture<Result<(), SetError>> {
+        let payload = match self.codec.serialize(value) {
+            Ok(payload) => payload,
+            Err(err) => return PkvFuture::ready(Err(err)),
+        };
+        let (payload, encoding) = self.pack(&payload);
+        let payload = payload.into_owned();
+        let backend = self.backend.clone();
+        let watchers = self.watchers.clone();
+        let key = key.as_ref().to_string();
+        self.io().run(move || {
+            let entry = Entry::written(&payload, encoding, None, None);
+            let bytes = crate::stamp_value(&*backend, &key, entry)?;
+            backend.set(&key, &bytes)?;
+            watchers.notify(&key, ChangeKind::Set);
+            Ok(())
+        })
+    }
+
+    /// Remove the value for the given key without blocking
+    ///
+    /// Like [`PkvStore::remove`].
+    pub fn remove_async(&mut self, key: impl AsRef<str>) -> PkvFuture<Result<(), RemoveError>> {
+        let backend = self.backend.clone();
+        let watchers = self.watchers.clone();
+        let key = key.as_ref().to_string();
+        self.io().run(move || {
+            if backend.delete(&key)? {
+                watchers.notify(&key, ChangeKind::Removed);
+            }
+            Ok(())
+        })
+    }
+
+    /// Clear all key values data without blocking
+    ///
+    /// Like [`PkvStore::clear`].
+    pub fn clear_async(&mut self) -> PkvFuture<Result<(), SetError>> {
+        let backend = self.backend.clone();
+        let watchers = self.watchers.clone();
+        self.io().run(move || {
+            backend.clear()?;
+            watchers.notify("", ChangeKind::Cleared);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,172 @@
+//! The storage backend trait, for plugging custom storage into a [`PkvStore`](crate::PkvStore)
+
+use std::fmt::Debug;
+use std::ops::Bound;
+use std::sync::mpsc::Sender;
+
+use crate::{CasError, Changed, GetError, RemoveError, SetError};
+
+/// Serialized `(key, value)` pairs, as yielded by [`PkvBackend::range`] and
+/// [`PkvBackend::scan_prefix`]
+pub type BackendEntries<'a> = Box<dyn Iterator<Item = Result<(String, Vec<u8>), GetError>> + 'a>;
+
+/// A single write in a batch, as passed to [`PkvBackend::write_batch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    /// Store the serialized value for the key
+    Set(String, Vec<u8>),
+    /// Remove the value for the key
+    Remove(String),
+    /// Remove all keys, including those set earlier in the batch
+    Clear,
+}
+
+/// A storage backend for [`PkvStore`](crate::PkvStore)
+///
+/// Backends only deal with keys and already serialized bytes, serialization is
+/// handled by [`PkvStore`](crate::PkvStore). The built-in redb, sled, RocksDB and `localStorage`
+/// stores implement this trait, and custom backends can be used with
+/// [`PkvStore::with_backend`](crate::PkvStore::with_backend).
+///
+/// All methods take `&self`, so backends that aren't internally synchronized need
+/// to use interior mutability. Methods with a default implementation can be
+/// overridden for efficiency, or to make them atomic.
+pub trait PkvBackend: Debug + Send + Sync {
+    /// Get the bytes for the given key, or `None` if the key doesn't exist
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError>;
+
+    /// Store the bytes for the given key, replacing any previous value
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError>;
+
+    /// Remove the value for the given key, returning the previous bytes if there were any
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError>;
+
+    /// Remove the value for the given key, returning `true` if there was one
+    ///
+    /// Used when the previous value isn't needed, so backends that transform the
+    /// bytes on the way out can skip that.
+    fn delete(&self, key: &str) -> Result<bool, RemoveError> {
+        Ok(self.remove(key)?.is_some())
+    }
+
+    /// Replace the bytes for the given key with `new`, but only if the current bytes
+    /// are `expected`
+    ///
+    /// `None` means the key doesn't exist, or should be removed. On a mismatch,
+    /// [`CasError::Mismatch`] is returned with the current bytes. This is what
+    /// [`PkvStore::update`](crate::PkvStore::update) and
+    /// [`PkvStore::compare_and_swap`](crate::PkvStore::compare_and_swap) are built on, so it
+    /// should be atomic.
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>>;
+
+    /// Iterate over all key value pairs with keys within `range`, in key order
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_>;
+
+    /// Returns `true` if the backend contains a value for the given key
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Iterate over all key value pairs where the key starts with `prefix`, in key order
+    fn scan_prefix(&self, prefix: &str) -> BackendEntries<'_> {
+        let range = (Bound::Included(prefix), Bound::Unbounded);
+        let prefix = prefix.to_string();
+        Box::new(self.range(range).take_while(move |entry| match entry {
+            Ok((key, _)) => key.starts_with(&prefix),
+            Err(_) => true,
+        }))
+    }
+
+    /// Iterate over all keys, in key order
+    fn keys(&self) -> Box<dyn Iterator<Item = Result<String, GetError>> + '_> {
+        Box::new(
+            self.range((Bound::Unbounded, Bound::Unbounded))
+                .map(|entry| entry.map(|(key, _)| key)),
+        )
+    }
+
+    /// Returns the number of keys
+    fn len(&self) -> Result<usize, GetError> {
+        let mut len = 0;
+        for key in self.keys() {
+            key?;
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    /// Returns `true` if there are no keys
+    fn is_empty(&self) -> Result<bool, GetError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Remove all keys that start with `prefix`
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
+        let keys = self
+            .scan_prefix(prefix)
+            .map(|entry| entry.map(|(key, _)| key));
+        let keys = keys.collect::<Result<Vec<_>, _>>().map_err(get_to_set)?;
+        for key in keys {
+            self.remove(&key).map_err(remove_to_set)?;
+        }
+        Ok(())
+    }
+
+    /// Remove all keys
+    fn clear(&self) -> Result<(), SetError> {
+        self.clear_prefix("")
+    }
+
+    /// Subscribe to sets and removals of keys starting with `prefix`, if the backend
+    /// supports it natively
+    ///
+    /// The changes should be sent to `sender` until it's disconnected. Returns `false`
+    /// if watching isn't supported, which is the default, in which case
+    /// [`PkvStore`](crate::PkvStore) reports the changes made through it instead.
+    fn watch_prefix(&self, prefix: &str, sender: Sender<Changed>) -> bool {
+        let _ = (prefix, sender);
+        false
+    }
+
+    /// Apply all operations, in order
+    ///
+    /// The default implementation applies them one at a time, so an error leaves
+    /// the earlier operations applied. Backends with transactions should override
+    /// this to apply them atomically.
+    fn write_batch(&self, ops: Vec<BatchOp>) -> Result<(), SetError> {
+        for op in ops {
+            match op {
+                BatchOp::Set(key, bytes) => self.set(&key, &bytes)?,
+                BatchOp::Remove(key) => {
+                    self.remove(&key).map_err(remove_to_set)?;
+                }
+                BatchOp::Clear => self.clear()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turns a read error during a write into a [`SetError`], keeping the source
+pub(crate) fn get_to_set(err: GetError) -> SetError {
+    match err {
+        GetError::Io(source) => SetError::Io(source),
+        GetError::Corruption(source) => SetError::Corruption(source),
+        err => SetError::Backend(err.into()),
+    }
+}
+
+/// Turns a removal error during a write into a [`SetError`], keeping the source
+pub(crate) fn remove_to_set(err: RemoveError) -> SetError {
+    match err {
+        RemoveError::Io(source) => SetError::Io(source),
+        RemoveError::Corruption(source) => SetError::Corruption(source),
+        RemoveError::ReadOnly => SetError::ReadOnly,
+        err => SetError::Backend(err.into()),
+    }
+}
@@ -0,0 +1,100 @@
+//! Atomic multi-key writes
+
+use serde::Serialize;
+
+use crate::envelope::Entry;
+use crate::{BatchOp, ChangeKind, PkvStore, SetError};
+
+/// A set of writes that are applied together with [`WriteBatch::commit`]
+///
+/// Operations are applied in the order they were added, so a `set` after a
+/// `clear` survives, while a `set` before it doesn't.
+///
+/// On the native backends the whole batch is committed atomically: either all
+/// of the operations are applied, or none of them are. On wasm, `localStorage`
+/// has no transactions, so the operations are applied one by one and a failure
+/// part way through leaves the earlier operations applied.
+///
+/// Created with [`PkvStore::batch`]. Dropping the batch without committing it
+/// discards the operations.
+#[must_use = "the batch does nothing unless committed"]
+pub struct WriteBatch<'a> {
+    store: &'a mut PkvStore,
+    ops: Vec<BatchOp>,
+}
+
+impl<'a> WriteBatch<'a> {
+    pub(crate) fn new(store: &'a mut PkvStore) -> Self {
+        Self {
+            store,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Serialize the value and store it when the batch is committed
+    ///
+    /// Serialization happens immediately, so errors are reported here rather
+    /// than on commit. The creation time of the current value, see
+    /// [`PkvStore::metadata`], is read immediately as well.
+    pub fn set<T: Serialize>(
+        &mut self,
+        key: impl AsRef<str>,
+        value: &T,
+    ) -> Result<&mut Self, SetError> {
+        let key = key.as_ref();
+        let payload = self.store.codec.serialize(value)?;
+        let (payload, encoding) = self.store.pack(&payload);
+        let entry = Entry::written(&payload, encoding, None, None);
+        let bytes = crate::stamp_value(&*self.store.backend, key, entry)?;
+        self.ops.push(BatchOp::Set(key.to_string(), bytes));
+        Ok(self)
+    }
+
+    /// More or less the same as `set::<String>`, but can take a `&str`
+    pub fn set_string(&mut self, key: impl AsRef<str>, value: &str) -> Result<&mut Self, SetError> {
+        self.set(key, &value)
+    }
+
+    /// Remove the value for the given key when the batch is committed
+    pub fn remove(&mut self, key: impl AsRef<str>) -> &mut Self {
+        self.ops.push(BatchOp::Remove(key.as_ref().to_string()));
+        self
+    }
+
+    /// Clear all key values when the batch is committed
+    pub fn clear(&mut self) -> &mut Self {
+        self.ops.push(BatchOp::Clear);
+        self
+    }
+
+    /// Returns the number of operations in the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns `true` if no operations have been added to the batch
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Apply all operations in the batch to the store
+    pub fn commit(self) -> Result<(), SetError> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        let changes: Vec<_> = self
+            .ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::Set(key, _) => (key.clone(), ChangeKind::Set),
+                BatchOp::Remove(key) => (key.clone(), ChangeKind::Removed),
+                BatchOp::Clear => (String::new(), ChangeKind::Cleared),
+            })
+            .collect();
+        self.store.backend.write_batch(self.ops)?;
+        for (key, kind) in changes {
+            self.store.watchers.notify(&key, kind);
+        }
+        Ok(())
+    }
+}
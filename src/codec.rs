@@ -0,0 +1,247 @@
+//! How values are serialized before they are handed to the backend
+
+use std::error::Error;
+use std::fmt::Debug;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{GetError, SetError};
+
+/// Deserializes a value from the [`erased_serde::Deserializer`] it's given, see [`Codec::decode`]
+pub type DecodeFn<'a> =
+    &'a mut dyn FnMut(&mut dyn erased_serde::Deserializer<'_>) -> Result<(), erased_serde::Error>;
+
+/// A serialization format for the values of a [`PkvStore`](crate::PkvStore)
+///
+/// The codec is picked per store with [`PkvStore::with_codec`](crate::PkvStore::with_codec).
+/// MessagePack is used by default, and JSON on wasm. Values are type-erased with
+/// [`erased_serde`], so the codec can be chosen at runtime.
+///
+/// The [id](Codec::id) of the codec is recorded with every value, so values written
+/// with another codec are reported as a [`GetError::Deserialization`] rather than
+/// decoded into garbage. Values written by versions of bevy_pkv from before the codec
+/// was recorded are decoded with the codec of the store.
+pub trait Codec: Debug + Send + Sync {
+    /// Identifies the codec in the header of stored values
+    ///
+    /// Ids below 64 are reserved for the built-in codecs.
+    fn id(&self) -> u8;
+
+    /// Whether structs are serialized as maps with field names, rather than as arrays,
+    /// which is recorded with every value
+    fn struct_map(&self) -> bool {
+        false
+    }
+
+    /// Serialize the value
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+    /// Deserialize the bytes, by handing a deserializer for them to `decode`
+    ///
+    /// ```rust
+    /// # use bevy_pkv::{erased_serde, Codec, DecodeFn};
+    /// # use std::error::Error;
+    /// #[derive(Debug)]
+    /// struct Json;
+    ///
+    /// impl Codec for Json {
+    ///     fn id(&self) -> u8 {
+    ///         100
+    ///     }
+    ///
+    ///     fn struct_map(&self) -> bool {
+    ///         true
+    ///     }
+    ///
+    ///     fn encode(
+    ///         &self,
+    ///         value: &dyn erased_serde::Serialize,
+    ///     ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    ///         Ok(serde_json::to_vec(value)?)
+    ///     }
+    ///
+    ///     fn decode(
+    ///         &self,
+    ///         bytes: &[u8],
+    ///         decode: DecodeFn<'_>,
+    ///     ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ///         let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    ///         decode(&mut <dyn erased_serde::Deserializer>::erase(&mut deserializer))?;
+    ///         Ok(deserializer.end()?)
+    ///     }
+    /// }
+    /// ```
+    fn decode(
+        &self,
+        bytes: &[u8],
+        decode: DecodeFn<'_>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+impl dyn Codec + '_ {
+    pub(crate) fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, SetError> {
+        let value: &dyn erased_serde::Serialize = &value;
+        self.encode(value).map_err(SetError::Serialization)
+    }
+
+    pub(crate) fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, GetError> {
+        let mut value = None;
+        self.decode(bytes, &mut |deserializer| {
+            value = Some(erased_serde::deserialize(deserializer)?);
+            Ok(())
+        })
+        .map_err(GetError::Deserialization)?;
+        value.ok_or_else(|| GetError::Deserialization("the codec didn't decode a value".into()))
+    }
+}
+
+/// MessagePack, using [`rmp_serde`], with structs serialized as maps so fields can be
+/// added and reordered
+///
+/// This is the default codec on native, and the one used by earlier versions of bevy_pkv.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+impl Codec for MessagePackCodec {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn struct_map(&self) -> bool {
+        true
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
+        serde::Serialize::serialize(value, &mut serializer)?;
+        Ok(serializer.into_inner())
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        decode: DecodeFn<'_>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(bytes);
+        decode(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))?;
+        Ok(())
+    }
+}
+
+/// JSON, using [`serde_json`], which is easy to read and edit by hand
+///
+/// This is the default codec on wasm, where values are stored in `localStorage`.
+/// Available on native with the `json` feature.
+#[cfg(any(feature = "json", wasm))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(any(feature = "json", wasm))]
+impl Codec for JsonCodec {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn struct_map(&self) -> bool {
+        true
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        decode: DecodeFn<'_>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        decode(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))?;
+        Ok(deserializer.end()?)
+    }
+}
+
+/// [RON](https://docs.rs/ron), which is easy to read and edit by hand, and closer to
+/// Rust syntax than JSON
+///
+/// Available with the `ron` feature.
+#[cfg(feature = "ron")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RonCodec;
+
+#[cfg(feature = "ron")]
+impl Codec for RonCodec {
+    fn id(&self) -> u8 {
+        3
+    }
+
+    fn struct_map(&self) -> bool {
+        true
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(ron::to_string(value)?.into_bytes())
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        decode: DecodeFn<'_>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut deserializer = ron::Deserializer::from_bytes(bytes)?;
+        decode(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))?;
+        Ok(deserializer.end()?)
+    }
+}
+
+/// [postcard](https://docs.rs/postcard), a compact binary format
+///
+/// Field names aren't stored, so fields can't be added, removed or reordered
+/// without migrating the stored values. Available with the `postcard` feature.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    fn id(&self) -> u8 {
+        4
+    }
+
+    fn encode(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(postcard::to_allocvec(value)?)
+    }
+
+    fn decode(
+        &self,
+        bytes: &[u8],
+        decode: DecodeFn<'_>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut deserializer = postcard::Deserializer::from_bytes(bytes);
+        decode(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))?;
+        Ok(())
+    }
+}
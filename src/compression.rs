@@ -0,0 +1,112 @@
+//! Compressing large values before they are handed to the backend
+
+use crate::GetError;
+
+/// LZ4 can't compress data by more than a factor of 255, so a larger decompressed
+/// size in a value means it's corrupted
+#[cfg(feature = "compression")]
+const MAX_LZ4_RATIO: usize = 255;
+/// zstd stores a run of up to 128 KiB of the same byte in 4 bytes, and can't do better
+#[cfg(all(feature = "compression", not(wasm)))]
+const MAX_ZSTD_RATIO: usize = 32 * 1024;
+
+/// The algorithm a value was compressed with, recorded in its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    /// zstd, used on native
+    Zstd,
+    /// LZ4, used on wasm, where zstd isn't available since it's written in C
+    Lz4,
+}
+
+impl Compression {
+    /// The algorithm new values are compressed with on this platform
+    #[cfg(all(feature = "compression", not(wasm)))]
+    const DEFAULT: Self = Self::Zstd;
+    #[cfg(all(feature = "compression", wasm))]
+    const DEFAULT: Self = Self::Lz4;
+
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::Zstd => 1,
+            Self::Lz4 => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self, GetError> {
+        match id {
+            1 => Ok(Self::Zstd),
+            2 => Ok(Self::Lz4),
+            _ => {
+                let err = format!("unknown compression algorithm {id} in value header");
+                Err(GetError::Corruption(err.into()))
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    pub(crate) fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>, GetError> {
+        match self {
+            #[cfg(all(feature = "compression", not(wasm)))]
+            Self::Zstd => {
+                let size = zstd::zstd_safe::get_frame_content_size(bytes)
+                    .ok()
+                    .flatten();
+                let size = checked_size(self, size, bytes.len() * MAX_ZSTD_RATIO)?;
+                zstd::bulk::decompress(bytes, size).map_err(|err| GetError::Corruption(err.into()))
+            }
+            #[cfg(feature = "compression")]
+            Self::Lz4 => {
+                let size = bytes.first_chunk().map(|size| u32::from_le_bytes(*size));
+                checked_size(self, size.map(u64::from), bytes.len() * MAX_LZ4_RATIO)?;
+                lz4_flex::decompress_size_prepended(bytes)
+                    .map_err(|err| GetError::Corruption(err.into()))
+            }
+            #[allow(unreachable_patterns)]
+            algorithm => {
+                let err = format!(
+                    "the value is compressed with {algorithm:?}, which isn't available \
+                     on this platform, or without the `compression` feature"
+                );
+                Err(GetError::Deserialization(err.into()))
+            }
+        }
+    }
+}
+
+/// Checks the decompressed size recorded in a compressed value, since it's allocated
+/// up front, and a corrupted value could claim any size
+#[cfg(feature = "compression")]
+fn checked_size(algorithm: Compression, size: Option<u64>, max: usize) -> Result<usize, GetError> {
+    match size.and_then(|size| usize::try_from(size).ok()) {
+        Some(size) if size <= max => Ok(size),
+        _ => {
+            let err = format!("invalid decompressed size in {algorithm:?} compressed value");
+            Err(GetError::Corruption(err.into()))
+        }
+    }
+}
+
+/// Compresses the payload if it's at least `threshold` bytes, and compressing
+/// actually makes it smaller
+#[cfg(feature = "compression")]
+pub(crate) fn compress(payload: &[u8], threshold: Option<usize>) -> Option<(Vec<u8>, Compression)> {
+    if threshold.is_none_or(|threshold| payload.len() < threshold) {
+        return None;
+    }
+    let compressed = match Compression::DEFAULT {
+        // compression is only an optimization, so a failure stores the value as it is
+        #[cfg(not(wasm))]
+        Compression::Zstd => zstd::bulk::compress(payload, 0).ok()?,
+        _ => lz4_flex::compress_prepend_size(payload),
+    };
+    (compressed.len() < payload.len()).then_some((compressed, Compression::DEFAULT))
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn compress(
+    _payload: &[u8],
+    _threshold: Option<usize>,
+) -> Option<(Vec<u8>, Compression)> {
+    None
+}
@@ -0,0 +1,317 @@
+//! Encrypting values, and optionally hashing keys, before they reach the backend
+
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+use crate::{
+    BackendEntries, BatchOp, CasError, Changed, GetError, PkvBackend, RemoveError, SetError,
+};
+
+/// The version of the layout of encrypted values, bumped whenever it changes
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+/// Settings for encrypting the values of a [`PkvStore`](crate::PkvStore), used with
+/// [`PkvStore::with_encryption`](crate::PkvStore::with_encryption)
+///
+/// Values are encrypted with ChaCha20-Poly1305, which also detects values that were
+/// tampered with, or moved to another key.
+#[derive(Clone)]
+pub struct Encryption {
+    key: [u8; 32],
+    hash_keys: bool,
+}
+
+impl Encryption {
+    /// Encrypt values with the given key
+    ///
+    /// Values written with one key can't be read with another, so the key needs to
+    /// stay the same between runs. Note that a key shipped with the game can be
+    /// extracted from it, so this keeps casual hex editing out rather than a
+    /// determined attacker.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            hash_keys: false,
+        }
+    }
+
+    /// Also replace the keys with a keyed hash, so they don't reveal what's stored
+    ///
+    /// The original key is stored encrypted along with the value, so everything
+    /// keeps working, but scans and ranges have to decrypt every value in the store.
+    pub fn with_hashed_keys(mut self) -> Self {
+        self.hash_keys = true;
+        self
+    }
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("hash_keys", &self.hash_keys)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypts values before handing them to the wrapped backend, and decrypts them
+/// when reading
+///
+/// Values are stored as the [`VERSION`], a random nonce, and the ciphertext, with
+/// the key in the backend as associated data. With hashed keys, the plaintext starts
+/// with the length of the original key as a little endian `u32`, and the key itself.
+pub(crate) struct EncryptedBackend {
+    inner: Arc<dyn PkvBackend>,
+    cipher: ChaCha20Poly1305,
+    /// The key for hashing keys, if they are hashed
+    key_hasher: Option<[u8; 32]>,
+}
+
+impl fmt::Debug for EncryptedBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedBackend")
+            .field("inner", &self.inner)
+            .field("hash_keys", &self.key_hasher.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedBackend {
+    pub(crate) fn new(inner: Arc<dyn PkvBackend>, encryption: Encryption) -> Self {
+        let cipher_key = blake3::derive_key("bevy_pkv value encryption", &encryption.key);
+        let key_hasher = encryption
+            .hash_keys
+            .then(|| blake3::derive_key("bevy_pkv key hashing", &encryption.key));
+        Self {
+            inner,
+            cipher: ChaCha20Poly1305::new(&cipher_key.into()),
+            key_hasher,
+        }
+    }
+
+    /// The key the value for `key` is stored under in the wrapped backend
+    fn backend_key(&self, key: &str) -> String {
+        match &self.key_hasher {
+            Some(key_hasher) => blake3::keyed_hash(key_hasher, key.as_bytes())
+                .to_hex()
+                .to_string(),
+            None => key.to_string(),
+        }
+    }
+
+    fn seal(&self, backend_key: &str, key: &str, value: &[u8]) -> Result<Vec<u8>, SetError> {
+        let mut plaintext = Vec::new();
+        if self.key_hasher.is_some() {
+            plaintext.extend((key.len() as u32).to_le_bytes());
+            plaintext.extend(key.as_bytes());
+        }
+        plaintext.extend(value);
+
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::fill(&mut nonce).map_err(|err| SetError::Backend(err.into()))?;
+        let payload = Payload {
+            msg: &plaintext,
+            aad: backend_key.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|err| SetError::Backend(err.to_string().into()))?;
+
+        let mut bytes = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        bytes.push(VERSION);
+        bytes.extend(nonce);
+        bytes.extend(ciphertext);
+        Ok(bytes)
+    }
+
+    /// Decrypts the bytes stored under `backend_key`, returning the original key and
+    /// the value, or `None` if the encryption key is wrong or the bytes were tampered with
+    fn open(&self, backend_key: &str, bytes: &[u8]) -> Option<(String, Vec<u8>)> {
+        let ([VERSION], rest) = bytes.split_first_chunk()? else {
+            return None;
+        };
+        let (nonce, ciphertext) = rest.split_first_chunk::<NONCE_LEN>()?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: backend_key.as_bytes(),
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .ok()?;
+        if self.key_hasher.is_none() {
+            return Some((backend_key.to_string(), plaintext));
+        }
+        let (key_len, rest) = plaintext.split_first_chunk()?;
+        let key_len = u32::from_le_bytes(*key_len) as usize;
+        let key = rest.get(..key_len)?;
+        let key = String::from_utf8(key.to_vec()).ok()?;
+        Some((key, rest[key_len..].to_vec()))
+    }
+
+    fn open_value(&self, backend_key: &str, bytes: &[u8]) -> Result<Vec<u8>, GetError> {
+        let (_, value) = self.open(backend_key, bytes).ok_or(GetError::Decryption)?;
+        Ok(value)
+    }
+}
+
+impl PkvBackend for EncryptedBackend {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        let backend_key = self.backend_key(key);
+        let bytes = self.inner.get(&backend_key)?;
+        bytes
+            .map(|bytes| self.open_value(&backend_key, &bytes))
+            .transpose()
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError> {
+        let backend_key = self.backend_key(key);
+        let bytes = self.seal(&backend_key, key, value)?;
+        self.inner.set(&backend_key, &bytes)
+    }
+
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        let backend_key = self.backend_key(key);
+        let bytes = self.inner.remove(&backend_key)?;
+        bytes
+            .map(|bytes| {
+                let (_, value) = self
+                    .open(&backend_key, &bytes)
+                    .ok_or(RemoveError::Decryption)?;
+                Ok(value)
+            })
+            .transpose()
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, RemoveError> {
+        self.inner.delete(&self.backend_key(key))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        let backend_key = self.backend_key(key);
+        let new = new
+            .map(|new| self.seal(&backend_key, key, new))
+            .transpose()?;
+        // the same value encrypts to different bytes every time, so compare the
+        // decrypted values and then swap based on the stored bytes, retrying if they
+        // changed in between
+        loop {
+            let current = self.inner.get(&backend_key)?;
+            let value = current
+                .as_deref()
+                .map(|bytes| self.open_value(&backend_key, bytes))
+                .transpose()?;
+            if value.as_deref() != expected {
+                return Err(CasError::Mismatch { current: value });
+            }
+            match self
+                .inner
+                .compare_and_swap(&backend_key, current.as_deref(), new.as_deref())
+            {
+                Err(CasError::Mismatch { .. }) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        if self.key_hasher.is_none() {
+            return Box::new(self.inner.range(range).map(|entry| {
+                let (key, bytes) = entry?;
+                let value = self.open_value(&key, &bytes)?;
+                Ok((key, value))
+            }));
+        }
+        // hashed keys are in no particular order, so every value has to be decrypted
+        // to find the keys within the range
+        let mut entries = Vec::new();
+        for entry in self.inner.range((Bound::Unbounded, Bound::Unbounded)) {
+            let entry = entry.and_then(|(backend_key, bytes)| {
+                self.open(&backend_key, &bytes).ok_or(GetError::Decryption)
+            });
+            match entry {
+                Ok((key, value)) if range.contains(&key.as_str()) => entries.push(Ok((key, value))),
+                Ok(_) => {}
+                Err(err) => entries.push(Err(err)),
+            }
+        }
+        entries.sort_by(|a, b| {
+            let key =
+                |entry: &Result<(String, _), _>| entry.as_ref().ok().map(|(key, _)| key.clone());
+            key(a).cmp(&key(b))
+        });
+        Box::new(entries.into_iter())
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        self.inner.contains_key(&self.backend_key(key))
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = Result<String, GetError>> + '_> {
+        if self.key_hasher.is_none() {
+            return self.inner.keys();
+        }
+        Box::new(
+            self.range((Bound::Unbounded, Bound::Unbounded))
+                .map(|entry| entry.map(|(key, _)| key)),
+        )
+    }
+
+    fn len(&self) -> Result<usize, GetError> {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, GetError> {
+        self.inner.is_empty()
+    }
+
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
+        if self.key_hasher.is_none() || prefix.is_empty() {
+            return self.inner.clear_prefix(prefix);
+        }
+        let keys = self
+            .scan_prefix(prefix)
+            .map(|entry| entry.map(|(key, _)| self.backend_key(&key)));
+        let keys = keys
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(crate::backend::get_to_set)?;
+        let ops = keys.into_iter().map(BatchOp::Remove).collect();
+        self.inner.write_batch(ops)
+    }
+
+    fn clear(&self) -> Result<(), SetError> {
+        self.inner.clear()
+    }
+
+    fn watch_prefix(&self, prefix: &str, sender: Sender<Changed>) -> bool {
+        // the wrapped backend only knows the hashed keys
+        self.key_hasher.is_none() && self.inner.watch_prefix(prefix, sender)
+    }
+
+    fn write_batch(&self, ops: Vec<BatchOp>) -> Result<(), SetError> {
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Set(key, value) => {
+                    let backend_key = self.backend_key(&key);
+                    let bytes = self.seal(&backend_key, &key, &value)?;
+                    Ok(BatchOp::Set(backend_key, bytes))
+                }
+                BatchOp::Remove(key) => Ok(BatchOp::Remove(self.backend_key(&key))),
+                BatchOp::Clear => Ok(BatchOp::Clear),
+            })
+            .collect::<Result<_, SetError>>()?;
+        self.inner.write_batch(ops)
+    }
+}
@@ -0,0 +1,266 @@
+//! Versioned header in front of stored values, describing how the value is encoded
+//! and holding per-entry metadata like the expiry time and when the value was written
+//!
+//! The header is laid out as:
+//!
+//! - [`MARKER`]
+//! - the header [`VERSION`]
+//! - a flags byte
+//! - the id of the codec the payload was serialized with
+//! - the compression algorithm, if [`COMPRESSED`] is set
+//! - the schema version, as a little endian `u32`, if [`SCHEMA`] is set
+//! - the times of the set time flags, as little endian `u64` milliseconds since the
+//!   unix epoch, in the order of the flags
+//! - the payload
+//!
+//! Values written before the header existed are stored as they are, and can still be read.
+
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::compression::Compression;
+use crate::GetError;
+
+/// First byte of a value with a header. It's never used by MessagePack, and isn't
+/// valid UTF-8, so it can't be the start of a value written before the header existed.
+const MARKER: u8 = 0xC1;
+
+/// The version of the header layout, bumped whenever it changes
+const VERSION: u8 = 1;
+
+/// The header holds the time the value expires
+const EXPIRES: u8 = 1 << 0;
+/// The header holds the time the key was first written
+const CREATED: u8 = 1 << 1;
+/// The header holds the time the value was last written
+const MODIFIED: u8 = 1 << 2;
+/// The payload is raw bytes stored with `PkvStore::set_raw`, rather than a serialized value
+const RAW: u8 = 1 << 3;
+/// Structs are serialized as maps with field names, rather than as arrays
+const STRUCT_MAP: u8 = 1 << 4;
+/// The header holds a schema version
+const SCHEMA: u8 = 1 << 5;
+/// The payload is compressed, and the header holds the algorithm
+const COMPRESSED: u8 = 1 << 6;
+
+const KNOWN_FLAGS: u8 = EXPIRES | CREATED | MODIFIED | RAW | STRUCT_MAP | SCHEMA | COMPRESSED;
+
+/// How the payload of an entry was serialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Encoding {
+    /// See [`Codec::id`](crate::Codec::id)
+    pub(crate) codec: u8,
+    pub(crate) struct_map: bool,
+    /// See [`PkvStore::with_schema_version`](crate::PkvStore::with_schema_version)
+    pub(crate) schema_version: Option<u32>,
+    /// See [`PkvStore::with_compression`](crate::PkvStore::with_compression)
+    pub(crate) compression: Option<Compression>,
+}
+
+/// A stored value, split into its metadata and the serialized payload
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry<'a> {
+    /// `None` if the value was written before the header existed
+    pub(crate) encoding: Option<Encoding>,
+    pub(crate) expires_at: Option<SystemTime>,
+    pub(crate) created: Option<SystemTime>,
+    pub(crate) modified: Option<SystemTime>,
+    pub(crate) raw: bool,
+    pub(crate) payload: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// An entry written now, for a key first written at `created`, or now for a new key
+    pub(crate) fn written(
+        payload: &'a [u8],
+        encoding: Encoding,
+        expires_at: Option<SystemTime>,
+        created: Option<SystemTime>,
+    ) -> Self {
+        let now = now();
+        Self {
+            encoding: Some(encoding),
+            expires_at,
+            created: Some(created.unwrap_or(now)),
+            modified: Some(now),
+            raw: false,
+            payload,
+        }
+    }
+
+    pub(crate) fn parse(bytes: &'a [u8]) -> Result<Self, GetError> {
+        let Some(([MARKER, version], rest)) = bytes.split_first_chunk() else {
+            return Ok(Self {
+                encoding: None,
+                expires_at: None,
+                created: None,
+                modified: None,
+                raw: false,
+                payload: bytes,
+            });
+        };
+        if *version != VERSION {
+            let err = format!(
+                "unsupported value header version {version}, \
+                 the value may have been written by a newer version of bevy_pkv"
+            );
+            return Err(GetError::Corruption(err.into()));
+        }
+        let truncated = || GetError::Corruption("truncated value header".into());
+        let Some(([flags, codec], mut rest)) = rest.split_first_chunk() else {
+            return Err(truncated());
+        };
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(GetError::Corruption("unknown flags in value header".into()));
+        }
+        let mut compression = None;
+        if flags & COMPRESSED != 0 {
+            let (algorithm, payload) = rest.split_first().ok_or_else(truncated)?;
+            compression = Some(Compression::from_id(*algorithm)?);
+            rest = payload;
+        }
+        let mut schema_version = None;
+        if flags & SCHEMA != 0 {
+            let (version, payload) = rest.split_first_chunk().ok_or_else(truncated)?;
+            schema_version = Some(u32::from_le_bytes(*version));
+            rest = payload;
+        }
+        let mut read_time = |flag: u8| -> Result<_, GetError> {
+            if flags & flag == 0 {
+                return Ok(None);
+            }
+            let (millis, payload) = rest.split_first_chunk().ok_or_else(truncated)?;
+            rest = payload;
+            let millis = u64::from_le_bytes(*millis);
+            Ok(Some(UNIX_EPOCH + Duration::from_millis(millis)))
+        };
+        let expires_at = read_time(EXPIRES)?;
+        let created = read_time(CREATED)?;
+        let modified = read_time(MODIFIED)?;
+        Ok(Self {
+            encoding: Some(Encoding {
+                codec: *codec,
+                struct_map: flags & STRUCT_MAP != 0,
+                schema_version,
+                compression,
+            }),
+            expires_at,
+            created,
+            modified,
+            raw: flags & RAW != 0,
+            payload: rest,
+        })
+    }
+
+    /// The length of the header in front of the payload, or `None` if the bytes have
+    /// no valid header
+    #[cfg_attr(not(wasm), allow(dead_code))]
+    pub(crate) fn header_len(bytes: &[u8]) -> Option<usize> {
+        let entry = Entry::parse(bytes).ok()?;
+        entry.encoding.map(|_| bytes.len() - entry.payload.len())
+    }
+
+    /// Puts the header in front of the payload, unless the entry was written before
+    /// the header existed
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let Some(encoding) = self.encoding else {
+            return self.payload.to_vec();
+        };
+        let times = [
+            (EXPIRES, self.expires_at),
+            (CREATED, self.created),
+            (MODIFIED, self.modified),
+        ];
+        let flags = [
+            (EXPIRES, self.expires_at.is_some()),
+            (CREATED, self.created.is_some()),
+            (MODIFIED, self.modified.is_some()),
+            (RAW, self.raw),
+            (STRUCT_MAP, encoding.struct_map),
+            (SCHEMA, encoding.schema_version.is_some()),
+            (COMPRESSED, encoding.compression.is_some()),
+        ];
+        let flags = flags
+            .into_iter()
+            .filter(|(_, set)| *set)
+            .fold(0, |flags, (flag, _)| flags | flag);
+
+        let mut bytes = Vec::with_capacity(self.payload.len() + 5 + 4 + 8 * 3);
+        bytes.extend([MARKER, VERSION, flags, encoding.codec]);
+        if let Some(compression) = encoding.compression {
+            bytes.push(compression.id());
+        }
+        if let Some(schema_version) = encoding.schema_version {
+            bytes.extend(schema_version.to_le_bytes());
+        }
+        for time in times.into_iter().filter_map(|(_, time)| time) {
+            let millis = time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+            bytes.extend(millis.to_le_bytes());
+        }
+        bytes.extend(self.payload);
+        bytes
+    }
+
+    /// Returns the payload, decompressed if it was compressed
+    pub(crate) fn decompressed(&self) -> Result<Cow<'a, [u8]>, GetError> {
+        match self.encoding.and_then(|encoding| encoding.compression) {
+            Some(compression) => compression.decompress(self.payload).map(Cow::Owned),
+            None => Ok(Cow::Borrowed(self.payload)),
+        }
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now())
+    }
+
+    /// Returns the payload, or `None` if the entry has expired
+    pub(crate) fn live(self) -> Option<&'a [u8]> {
+        (!self.is_expired()).then_some(self.payload)
+    }
+}
+
+/// Metadata about a stored value, as returned by [`PkvStore::metadata`](crate::PkvStore::metadata)
+///
+/// Values written by versions of bevy_pkv from before the metadata existed only
+/// have an `encoded_len`, the other fields are `None` for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// When the key was first written
+    pub created: Option<SystemTime>,
+    /// When the value was last written
+    pub modified: Option<SystemTime>,
+    /// The size of the value in bytes as it's stored, after compression and not
+    /// counting the metadata
+    pub encoded_len: usize,
+    /// The [id](crate::Codec::id) of the codec the value was serialized with
+    pub codec_id: Option<u8>,
+    /// Whether structs were serialized as maps with field names, rather than as arrays
+    pub struct_map: Option<bool>,
+    /// The schema version the value was written with, see
+    /// [`PkvStore::with_schema_version`](crate::PkvStore::with_schema_version)
+    pub schema_version: Option<u32>,
+}
+
+impl From<Entry<'_>> for EntryMetadata {
+    fn from(entry: Entry<'_>) -> Self {
+        Self {
+            created: entry.created,
+            modified: entry.modified,
+            encoded_len: entry.payload.len(),
+            codec_id: entry.encoding.map(|encoding| encoding.codec),
+            struct_map: entry.encoding.map(|encoding| encoding.struct_map),
+            schema_version: entry.encoding.and_then(|encoding| encoding.schema_version),
+        }
+    }
+}
+
+/// The current time. `SystemTime::now` panics on wasm, so the time is read from JavaScript there.
+pub(crate) fn now() -> SystemTime {
+    #[cfg(wasm)]
+    return UNIX_EPOCH + Duration::from_millis(js_sys::Date::now() as u64);
+    #[cfg(not(wasm))]
+    SystemTime::now()
+}
@@ -0,0 +1,258 @@
+//! Error types shared by all backends
+
+use std::error::Error;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// The kind of an error, independent of the backend in use
+///
+/// Useful for handling errors without matching on every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The value for the given key was not found
+    NotFound,
+    /// The value could not be serialized
+    Serialization,
+    /// The stored value could not be deserialized as the requested type
+    Deserialization,
+    /// The backend failed to read or write its files
+    Io,
+    /// The backend detected corrupted data
+    Corruption,
+    /// Any other error from the backend
+    Backend,
+    /// The store was opened read-only, so it can't be written to
+    ReadOnly,
+    /// The value couldn't be decrypted, because the encryption key is wrong or the
+    /// value was tampered with
+    Decryption,
+}
+
+/// Errors that can occur during `PkvStore::get`
+#[derive(thiserror::Error, Debug)]
+pub enum GetError {
+    /// The value for the given key was not found
+    #[error("No value found for the given key")]
+    NotFound,
+    /// The value was not stored with `PkvStore::set_raw`
+    #[error("Value is not a raw value")]
+    NotRaw,
+    /// Error when deserializing the value
+    #[error("Deserialization error")]
+    Deserialization(#[source] BoxError),
+    /// The value couldn't be decrypted, see `PkvStore::with_encryption`
+    #[error("Decryption error, the encryption key is wrong or the value was tampered with")]
+    Decryption,
+    /// The backend failed to read its files
+    #[error("I/O error")]
+    Io(#[source] BoxError),
+    /// The backend detected corrupted data
+    #[error("Corrupted data")]
+    Corruption(#[source] BoxError),
+    /// Any other error from the backend
+    #[error("Backend error")]
+    Backend(#[source] BoxError),
+}
+
+impl GetError {
+    /// The kind of error, independent of the backend
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::NotFound => ErrorKind::NotFound,
+            Self::NotRaw | Self::Deserialization(_) => ErrorKind::Deserialization,
+            Self::Decryption => ErrorKind::Decryption,
+            Self::Io(_) => ErrorKind::Io,
+            Self::Corruption(_) => ErrorKind::Corruption,
+            Self::Backend(_) => ErrorKind::Backend,
+        }
+    }
+
+    pub(crate) fn from_backend(kind: ErrorKind, source: BoxError) -> Self {
+        match kind {
+            ErrorKind::Io => Self::Io(source),
+            ErrorKind::Corruption => Self::Corruption(source),
+            _ => Self::Backend(source),
+        }
+    }
+}
+
+/// Errors that can occur during `PkvStore::set`
+#[derive(thiserror::Error, Debug)]
+pub enum SetError {
+    /// Error when serializing the value
+    #[error("Serialization error")]
+    Serialization(#[source] BoxError),
+    /// The store was opened with `PkvStore::open_read_only`
+    #[error("The store is read-only")]
+    ReadOnly,
+    /// The backend failed to write its files
+    #[error("I/O error")]
+    Io(#[source] BoxError),
+    /// The backend detected corrupted data
+    #[error("Corrupted data")]
+    Corruption(#[source] BoxError),
+    /// Any other error from the backend
+    #[error("Backend error")]
+    Backend(#[source] BoxError),
+}
+
+impl SetError {
+    /// The kind of error, independent of the backend
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Serialization(_) => ErrorKind::Serialization,
+            Self::ReadOnly => ErrorKind::ReadOnly,
+            Self::Io(_) => ErrorKind::Io,
+            Self::Corruption(_) => ErrorKind::Corruption,
+            Self::Backend(_) => ErrorKind::Backend,
+        }
+    }
+
+    pub(crate) fn from_backend(kind: ErrorKind, source: BoxError) -> Self {
+        match kind {
+            ErrorKind::Io => Self::Io(source),
+            ErrorKind::Corruption => Self::Corruption(source),
+            _ => Self::Backend(source),
+        }
+    }
+}
+
+/// Errors that can occur during `PkvStore::remove` and `PkvStore::remove_and_get`
+#[derive(thiserror::Error, Debug)]
+pub enum RemoveError {
+    /// Error when deserializing the removed value
+    #[error("Deserialization error")]
+    Deserialization(#[source] BoxError),
+    /// The value removed with `PkvStore::remove_and_get` couldn't be decrypted, see
+    /// `PkvStore::with_encryption`. It's removed anyway.
+    #[error("Decryption error, the encryption key is wrong or the value was tampered with")]
+    Decryption,
+    /// The store was opened with `PkvStore::open_read_only`
+    #[error("The store is read-only")]
+    ReadOnly,
+    /// The backend failed to write its files
+    #[error("I/O error")]
+    Io(#[source] BoxError),
+    /// The backend detected corrupted data
+    #[error("Corrupted data")]
+    Corruption(#[source] BoxError),
+    /// Any other error from the backend
+    #[error("Backend error")]
+    Backend(#[source] BoxError),
+}
+
+impl RemoveError {
+    /// The kind of error, independent of the backend
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Deserialization(_) => ErrorKind::Deserialization,
+            Self::Decryption => ErrorKind::Decryption,
+            Self::ReadOnly => ErrorKind::ReadOnly,
+            Self::Io(_) => ErrorKind::Io,
+            Self::Corruption(_) => ErrorKind::Corruption,
+            Self::Backend(_) => ErrorKind::Backend,
+        }
+    }
+
+    pub(crate) fn from_backend(kind: ErrorKind, source: BoxError) -> Self {
+        match kind {
+            ErrorKind::Io => Self::Io(source),
+            ErrorKind::Corruption => Self::Corruption(source),
+            _ => Self::Backend(source),
+        }
+    }
+}
+
+/// Implements `From` for [`GetError`], [`SetError`] and [`RemoveError`] for the given
+/// backend error types, using `$classify` to turn them into an [`ErrorKind`] and source
+macro_rules! impl_from_backend_errors {
+    ($classify:path: $($error:ty),+ $(,)?) => {
+        $(
+            impl From<$error> for $crate::GetError {
+                fn from(err: $error) -> Self {
+                    let (kind, source) = $classify(err);
+                    Self::from_backend(kind, source)
+                }
+            }
+
+            impl From<$error> for $crate::SetError {
+                fn from(err: $error) -> Self {
+                    let (kind, source) = $classify(err);
+                    Self::from_backend(kind, source)
+                }
+            }
+
+            impl From<$error> for $crate::RemoveError {
+                fn from(err: $error) -> Self {
+                    let (kind, source) = $classify(err);
+                    Self::from_backend(kind, source)
+                }
+            }
+        )+
+    };
+}
+
+pub(crate) use impl_from_backend_errors;
+
+/// Errors that can occur when opening a `PkvStore`
+#[derive(thiserror::Error, Debug)]
+pub enum OpenError {
+    /// Error when creating the directory for the store, or reading or writing its files
+    #[error("I/O error when opening the store")]
+    Io(#[from] std::io::Error),
+    /// The store is locked, usually because it's already open in another process
+    ///
+    /// See [`LockPolicy`](crate::LockPolicy) for waiting until it's released instead.
+    #[error("The store is already open in another process, see {}", path.display())]
+    Locked {
+        /// The file the backend failed to lock
+        path: std::path::PathBuf,
+        /// The error from the backend
+        #[source]
+        source: BoxError,
+    },
+    /// The files of the store are corrupted
+    #[error("The store is corrupted")]
+    Corruption(#[source] BoxError),
+    /// Any other internal error from the backend
+    #[error("Backend error when opening the store")]
+    Backend(#[source] BoxError),
+}
+
+/// Errors that can occur during `PkvStore::update` and `PkvStore::get_or_insert_with`
+#[derive(thiserror::Error, Debug)]
+pub enum UpdateError {
+    /// Error when reading or deserializing the current value
+    #[error("Failed to get the current value")]
+    Get(#[from] GetError),
+    /// Error when serializing or storing the new value
+    #[error("Failed to set the new value")]
+    Set(#[from] SetError),
+}
+
+impl UpdateError {
+    /// The kind of error, independent of the backend
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Get(err) => err.kind(),
+            Self::Set(err) => err.kind(),
+        }
+    }
+}
+
+/// Errors that can occur during `PkvStore::compare_and_swap`
+#[derive(thiserror::Error, Debug)]
+pub enum CasError<T> {
+    /// The stored value did not match the expected value, so nothing was written
+    #[error("The current value did not match the expected value")]
+    Mismatch {
+        /// The value currently stored for the key, or `None` if there is none
+        current: Option<T>,
+    },
+    /// Error when reading or deserializing the current value
+    #[error("Failed to get the current value")]
+    Get(#[from] GetError),
+    /// Error when serializing or storing the new value
+    #[error("Failed to set the new value")]
+    Set(#[from] SetError),
+}
@@ -0,0 +1,57 @@
+//! Keys with the type of their value attached
+
+use std::{fmt, marker::PhantomData};
+
+/// A key that knows the type of its value
+///
+/// Defining keys as constants ties each key to its value type, so the compiler
+/// catches reading a key as the wrong type, and the type doesn't have to be spelled
+/// out at every use. Typed keys only work with the `_typed` methods, so they can't be
+/// read as another type through the untyped methods like [`PkvStore::get`](crate::PkvStore::get).
+///
+/// ```rust no_run
+/// use bevy_pkv::{PkvKey, PkvStore};
+///
+/// const USERNAME: PkvKey<String> = PkvKey::new("username");
+/// const HIGH_SCORE: PkvKey<u32> = PkvKey::new("high_score");
+///
+/// let mut store = PkvStore::new("FooCompany", "BarGame");
+/// store.set_typed(USERNAME, &"alice".to_string())?;
+/// let high_score = store.get_typed(HIGH_SCORE).unwrap_or(0);
+/// # Ok::<(), bevy_pkv::SetError>(())
+/// ```
+pub struct PkvKey<T> {
+    key: &'static str,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> PkvKey<T> {
+    /// Create a key for values of type `T`
+    pub const fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            _value: PhantomData,
+        }
+    }
+
+    /// The key as stored in the store
+    pub const fn as_str(&self) -> &'static str {
+        self.key
+    }
+}
+
+// implemented manually, since derives would require `T` to implement the traits as well
+
+impl<T> Clone for PkvKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PkvKey<T> {}
+
+impl<T> fmt::Debug for PkvKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PkvKey").field(&self.key).finish()
+    }
+}
@@ -1,15 +1,56 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
 
-#[cfg(all(rocksdb_backend, sled_backend, redb_backend))]
-compile_error!(
-    "the \"rocksdb\", \"redb\" and \"sled\" features may not be enabled at the same time"
-);
-
 #[cfg(not(any(rocksdb_backend, sled_backend, redb_backend, wasm)))]
 compile_error!("either the \"rocksdb\", \"redb\" or \"sled\" feature must be enabled on native");
 
 use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Cow;
+use std::ops::{Bound, RangeBounds};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "async")]
+mod async_io;
+mod backend;
+mod batch;
+mod codec;
+mod compression;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod envelope;
+mod error;
+
+#[cfg(feature = "async")]
+pub use async_io::PkvFuture;
+pub use backend::{BackendEntries, BatchOp, PkvBackend};
+pub use batch::WriteBatch;
+#[cfg(any(feature = "json", wasm))]
+pub use codec::JsonCodec;
+#[cfg(feature = "postcard")]
+pub use codec::PostcardCodec;
+#[cfg(feature = "ron")]
+pub use codec::RonCodec;
+pub use codec::{Codec, DecodeFn, MessagePackCodec};
+#[cfg(feature = "encryption")]
+pub use encryption::Encryption;
+pub use envelope::EntryMetadata;
+use envelope::{Encoding, Entry};
+/// Re-exported for implementing [`Codec`]
+pub use erased_serde;
+pub use error::{CasError, ErrorKind, GetError, OpenError, RemoveError, SetError, UpdateError};
+
+mod key;
+mod lock;
+mod namespace;
+mod watch;
+
+pub use key::PkvKey;
+pub use lock::LockPolicy;
+pub use namespace::Namespace;
+use watch::Watchers;
+pub use watch::{ChangeKind, Changed};
 
 #[cfg(feature = "bevy")]
 mod persistent_resource;
@@ -19,65 +60,136 @@ pub use persistent_resource::{PersistentResourceAppExtensions, PersistentResourc
 
 pub mod prelude;
 
-trait StoreImpl {
-    type GetError;
-    type SetError;
-    type RemoveError;
-
-    fn set_string(&mut self, key: &str, value: &str) -> Result<(), Self::SetError> {
-        self.set(key, &value.to_string())
-    }
-    fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, Self::GetError>;
-    fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), Self::SetError>;
-    fn remove(&mut self, key: &str) -> Result<(), Self::RemoveError>;
-    fn remove_and_get<T: DeserializeOwned>(
-        &mut self,
-        key: &str,
-    ) -> Result<Option<T>, Self::RemoveError>;
-    fn clear(&mut self) -> Result<(), Self::SetError>;
-}
+/// Lazily deserialized `(key, value)` pairs, as yielded by range and prefix scans
+type Entries<'a, T> =
+    Box<dyn Iterator<Item = Result<(String, Result<T, GetError>), GetError>> + 'a>;
 
 #[cfg(wasm)]
 mod local_storage_store;
 
 #[cfg(wasm)]
-use local_storage_store::{self as backend};
+pub use local_storage_store::LocalStorageStore;
+
+mod memory_store;
+mod read_only;
+
+pub use memory_store::MemoryStore;
+
+#[cfg(redb_backend)]
+mod redb_store;
+
+#[cfg(redb_backend)]
+pub use redb_store::ReDbStore;
 
 #[cfg(sled_backend)]
 mod sled_store;
 
 #[cfg(sled_backend)]
-use sled_store::{self as backend};
+pub use sled_store::SledStore;
 
 #[cfg(rocksdb_backend)]
 mod rocksdb_store;
 
 #[cfg(rocksdb_backend)]
-use rocksdb_store::{self as backend};
+pub use rocksdb_store::RocksDBStore;
+
+// The backend used by `PkvStore::new` and friends. When several backend features are
+// enabled, redb is preferred over sled, and sled over rocksdb.
+
+#[cfg(wasm)]
+type DefaultBackend = local_storage_store::LocalStorageStore;
+
+#[cfg(redb_backend)]
+type DefaultBackend = ReDbStore;
+
+#[cfg(all(sled_backend, not(redb_backend)))]
+type DefaultBackend = SledStore;
 
-// todo: Look into unifying these types?
-pub use backend::{GetError, RemoveError, SetError};
+#[cfg(all(rocksdb_backend, not(any(redb_backend, sled_backend))))]
+type DefaultBackend = RocksDBStore;
 
+#[derive(Clone, Copy)]
 enum Location<'a> {
     PlatformDefault(&'a PlatformDefault),
     #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
     CustomPath(&'a std::path::Path),
 }
 
-#[cfg(redb_backend)]
-mod redb_store;
-
-#[cfg(redb_backend)]
-use redb_store::{self as backend};
-
 #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
 mod path;
 
+/// Reads the value for the key from the backend, treating expired values as missing
+fn read_value<T: DeserializeOwned>(
+    backend: &dyn PkvBackend,
+    codec: &dyn Codec,
+    key: &str,
+) -> Result<T, GetError> {
+    let bytes = backend.get(key)?.ok_or(GetError::NotFound)?;
+    let entry = Entry::parse(&bytes)?;
+    if entry.is_expired() {
+        return Err(GetError::NotFound);
+    }
+    decode_entry(codec, entry)
+}
+
+/// Deserializes the payload of the entry, which must not be a raw value, and must have
+/// been written with the same codec
+fn decode_entry<T: DeserializeOwned>(codec: &dyn Codec, entry: Entry) -> Result<T, GetError> {
+    check_decodable(codec, entry)?;
+    codec.deserialize(&entry.decompressed()?)
+}
+
+/// Checks that the entry isn't a raw value, and was written with the given codec
+fn check_decodable(codec: &dyn Codec, entry: Entry) -> Result<(), GetError> {
+    if entry.raw {
+        let err = "the value was stored with `set_raw`, and can only be read with `get_raw`";
+        return Err(GetError::Deserialization(err.into()));
+    }
+    if let Some(encoding) = entry
+        .encoding
+        .filter(|encoding| encoding.codec != codec.id())
+    {
+        let err = format!(
+            "the value was written with the codec with id {}, but the store uses {:?}",
+            encoding.codec, codec
+        );
+        return Err(GetError::Deserialization(err.into()));
+    }
+    Ok(())
+}
+
+/// Puts the metadata in front of the serialized value, keeping the creation time of
+/// the current value for the key
+///
+/// This reads the current value before every write, which isn't atomic with the write,
+/// so a concurrent write from another process can reset the creation time.
+fn stamp_value(backend: &dyn PkvBackend, key: &str, mut entry: Entry) -> Result<Vec<u8>, SetError> {
+    // a broken, undecryptable or expired value is overwritten as if the key was new
+    let current = match backend.get(key) {
+        Err(GetError::Decryption) => None,
+        current => current.map_err(backend::get_to_set)?,
+    };
+    let current = current
+        .as_deref()
+        .and_then(|bytes| Entry::parse(bytes).ok());
+    let created = current
+        .filter(|entry| !entry.is_expired())
+        .and_then(|entry| entry.created);
+    entry.created = created.or(entry.created);
+    Ok(entry.to_bytes())
+}
+
 /// Main resource for setting/getting values
 #[derive(Debug)]
 #[cfg_attr(feature = "bevy", derive(bevy_ecs::prelude::Resource))]
 pub struct PkvStore {
-    inner: backend::InnerStore,
+    backend: Arc<dyn PkvBackend>,
+    codec: Arc<dyn Codec>,
+    schema_version: Option<u32>,
+    compress_above: Option<usize>,
+    watchers: Arc<Watchers>,
+    #[cfg(feature = "async")]
+    io: std::sync::OnceLock<async_io::IoThread>,
 }
 
 #[allow(clippy::result_large_err)]
@@ -86,13 +198,26 @@ impl PkvStore {
     ///
     /// The given `organization` and `application` are used to create a backing file
     /// in a corresponding location on the users device. Usually within the home or user folder
+    ///
+    /// # Panics
+    ///
+    /// Panics if the store can't be opened, see [`PkvStore::try_new`] for a fallible version.
     pub fn new(organization: &str, application: &str) -> Self {
+        Self::try_new(organization, application).expect("Failed to init key value store")
+    }
+
+    /// Creates or opens a persistent key value store
+    ///
+    /// Like [`PkvStore::new`], but returns an error instead of panicking if the store
+    /// can't be opened, e.g. because the directory is read-only or the store is locked
+    /// by another process.
+    pub fn try_new(organization: &str, application: &str) -> Result<Self, OpenError> {
         let config = PlatformDefault {
             qualifier: None,
             organization: organization.to_string(),
             application: application.to_string(),
         };
-        Self::new_in_location(&config)
+        Self::try_new_in_location(&config)
     }
 
     /// Creates or opens a persistent key value store
@@ -101,12 +226,24 @@ impl PkvStore {
     /// Some operating systems use the qualifier as part of the path to the store.
     /// The qualifier is usually "com", "org" etc.
     pub fn new_with_qualifier(qualifier: &str, organization: &str, application: &str) -> Self {
+        Self::try_new_with_qualifier(qualifier, organization, application)
+            .expect("Failed to init key value store")
+    }
+
+    /// Creates or opens a persistent key value store
+    ///
+    /// Like [`PkvStore::new_with_qualifier`], but returns an error instead of panicking.
+    pub fn try_new_with_qualifier(
+        qualifier: &str,
+        organization: &str,
+        application: &str,
+    ) -> Result<Self, OpenError> {
         let config = PlatformDefault {
             qualifier: Some(qualifier.to_string()),
             organization: organization.to_string(),
             application: application.to_string(),
         };
-        Self::new_in_location(&config)
+        Self::try_new_in_location(&config)
     }
 
     /// Creates or opens a persistent key value store
@@ -116,8 +253,16 @@ impl PkvStore {
     /// in a corresponding location on the users device.
     #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
     pub fn new_in_dir<P: AsRef<std::path::Path>>(path: P) -> Self {
-        let inner = backend::InnerStore::new(Location::CustomPath(path.as_ref()));
-        Self { inner }
+        Self::try_new_in_dir(path).expect("Failed to init key value store")
+    }
+
+    /// Creates or opens a persistent key value store
+    ///
+    /// Like [`PkvStore::new_in_dir`], but returns an error instead of panicking.
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    pub fn try_new_in_dir<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        let backend = DefaultBackend::new(Location::CustomPath(path.as_ref()))?;
+        Ok(Self::from_default_backend(backend))
     }
 
     /// Creates or opens a persistent key value store
@@ -125,49 +270,759 @@ impl PkvStore {
     /// Like [`PkvStore::new_in_dir`], but allows specifying a filename.
     #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
     pub fn new_in_dir_with_filename<P: AsRef<std::path::Path>>(path: P, filename: &str) -> Self {
-        let inner =
-            backend::InnerStore::new_with_filename(Location::CustomPath(path.as_ref()), filename);
-        Self { inner }
+        Self::try_new_in_dir_with_filename(path, filename).expect("Failed to init key value store")
+    }
+
+    /// Creates or opens a persistent key value store
+    ///
+    /// Like [`PkvStore::new_in_dir_with_filename`], but returns an error instead of panicking.
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    pub fn try_new_in_dir_with_filename<P: AsRef<std::path::Path>>(
+        path: P,
+        filename: &str,
+    ) -> Result<Self, OpenError> {
+        let location = Location::CustomPath(path.as_ref());
+        let backend = DefaultBackend::new_with_filename(location, filename)?;
+        Ok(Self::from_default_backend(backend))
+    }
+
+    /// Creates or opens a persistent key value store, deciding what to do if it's
+    /// locked by another process
+    ///
+    /// Like [`PkvStore::try_new`], which is the same as using [`LockPolicy::Fail`].
+    /// If the store is opened read-only because of [`LockPolicy::ReadOnly`], writes
+    /// return a `ReadOnly` error, e.g. [`SetError::ReadOnly`].
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::{LockPolicy, PkvStore};
+    /// # use std::time::Duration;
+    /// let policy = LockPolicy::ReadOnly {
+    ///     timeout: Duration::from_secs(1),
+    /// };
+    /// let store = PkvStore::try_new_with_lock_policy("FooCompany", "BarGame", policy)?;
+    /// # Ok::<(), bevy_pkv::OpenError>(())
+    /// ```
+    pub fn try_new_with_lock_policy(
+        organization: &str,
+        application: &str,
+        policy: LockPolicy,
+    ) -> Result<Self, OpenError> {
+        let config = PlatformDefault {
+            qualifier: None,
+            organization: organization.to_string(),
+            application: application.to_string(),
+        };
+        Self::open_with_lock_policy(Location::PlatformDefault(&config), policy)
+    }
+
+    /// Creates or opens a persistent key value store, deciding what to do if it's
+    /// locked by another process
+    ///
+    /// Like [`PkvStore::try_new_with_lock_policy`], but requires a direct path.
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    pub fn try_new_in_dir_with_lock_policy<P: AsRef<std::path::Path>>(
+        path: P,
+        policy: LockPolicy,
+    ) -> Result<Self, OpenError> {
+        Self::open_with_lock_policy(Location::CustomPath(path.as_ref()), policy)
+    }
+
+    fn try_new_in_location(config: &PlatformDefault) -> Result<Self, OpenError> {
+        let backend = DefaultBackend::new(Location::PlatformDefault(config))?;
+        Ok(Self::from_default_backend(backend))
+    }
+
+    /// Opens an existing store for reading only
+    ///
+    /// Uses the same location as [`PkvStore::new`]. The store is opened in the backend's
+    /// read-only mode where supported, and nothing is created if it doesn't exist,
+    /// [`OpenError::Io`] is returned instead. All methods that would write to the
+    /// store return a `ReadOnly` error, e.g. [`SetError::ReadOnly`].
+    ///
+    /// sled has no read-only mode, so it's opened normally, but still never written to.
+    pub fn open_read_only(organization: &str, application: &str) -> Result<Self, OpenError> {
+        let config = PlatformDefault {
+            qualifier: None,
+            organization: organization.to_string(),
+            application: application.to_string(),
+        };
+        let backend = DefaultBackend::new_read_only(Location::PlatformDefault(&config))?;
+        Ok(Self::from_default_backend(read_only::ReadOnlyBackend(
+            backend,
+        )))
+    }
+
+    /// Opens an existing store in the given directory for reading only
+    ///
+    /// Like [`PkvStore::open_read_only`], but requires a direct path.
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    pub fn open_read_only_in_dir<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        let backend = DefaultBackend::new_read_only(Location::CustomPath(path.as_ref()))?;
+        Ok(Self::from_default_backend(read_only::ReadOnlyBackend(
+            backend,
+        )))
     }
 
-    fn new_in_location(config: &PlatformDefault) -> Self {
-        let inner = backend::InnerStore::new(Location::PlatformDefault(config));
-        Self { inner }
+    fn from_default_backend(backend: impl PkvBackend + 'static) -> Self {
+        // local storage can only hold strings, so values are stored as json there
+        #[cfg(wasm)]
+        let codec = Arc::new(JsonCodec);
+        #[cfg(not(wasm))]
+        let codec = Arc::new(MessagePackCodec);
+        Self {
+            backend: Arc::new(backend),
+            codec,
+            schema_version: None,
+            compress_above: None,
+            watchers: Arc::default(),
+            #[cfg(feature = "async")]
+            io: Default::default(),
+        }
+    }
+
+    /// Opens a store backed by redb in the given directory
+    ///
+    /// Unlike [`PkvStore::try_new_in_dir`], this uses redb even if other backend
+    /// features are enabled. See [`ReDbStore::open`].
+    #[cfg(redb_backend)]
+    pub fn open_redb<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        Ok(Self::with_backend(Box::new(ReDbStore::open(path)?)))
+    }
+
+    /// Opens a store backed by sled in the given directory
+    ///
+    /// Unlike [`PkvStore::try_new_in_dir`], this uses sled even if other backend
+    /// features are enabled. See [`SledStore::open`].
+    #[cfg(sled_backend)]
+    pub fn open_sled<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        Ok(Self::with_backend(Box::new(SledStore::open(path)?)))
+    }
+
+    /// Opens a store backed by RocksDB in the given directory
+    ///
+    /// Unlike [`PkvStore::try_new_in_dir`], this uses RocksDB even if other backend
+    /// features are enabled. See [`RocksDBStore::open`].
+    #[cfg(rocksdb_backend)]
+    pub fn open_rocksdb<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        Ok(Self::with_backend(Box::new(RocksDBStore::open(path)?)))
+    }
+
+    /// Creates a store that keeps the data in memory, without persisting it
+    ///
+    /// Useful for tests and headless servers. Values are serialized as MessagePack,
+    /// just like the native backends, so behavior matches. See [`MemoryStore`].
+    pub fn in_memory() -> Self {
+        Self::with_backend(Box::new(MemoryStore::new()))
+    }
+
+    /// Creates a store on top of a custom backend
+    ///
+    /// Values are serialized as MessagePack before they are handed to the backend,
+    /// see [`PkvStore::with_codec`] to change that. See [`PkvBackend`] for how to
+    /// implement a backend.
+    pub fn with_backend(backend: Box<dyn PkvBackend>) -> Self {
+        Self {
+            backend: backend.into(),
+            codec: Arc::new(MessagePackCodec),
+            schema_version: None,
+            compress_above: None,
+            watchers: Arc::default(),
+            #[cfg(feature = "async")]
+            io: Default::default(),
+        }
+    }
+
+    /// Use the given codec to serialize values
+    ///
+    /// Values already in the store aren't converted, so they can't be read unless
+    /// they were written with the same codec.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::{MessagePackCodec, PkvStore};
+    /// let store = PkvStore::new("FooCompany", "BarGame").with_codec(MessagePackCodec);
+    /// ```
+    pub fn with_codec(mut self, codec: impl Codec + 'static) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Record the given version of your data layout with every value written
+    ///
+    /// bevy_pkv doesn't interpret the version, it can be read back with
+    /// [`PkvStore::metadata`] to decide whether a value needs to be migrated.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::PkvStore;
+    /// # #[derive(serde::Deserialize)] struct Settings;
+    /// # fn migrate_settings_v1(store: &PkvStore) -> Settings { Settings }
+    /// let store = PkvStore::new("FooCompany", "BarGame").with_schema_version(2);
+    /// let settings = match store.metadata("settings")?.schema_version {
+    ///     Some(2) => store.get::<Settings>("settings")?,
+    ///     _ => migrate_settings_v1(&store),
+    /// };
+    /// # Ok::<(), bevy_pkv::GetError>(())
+    /// ```
+    pub fn with_schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    /// Compress values of at least `threshold` bytes before storing them
+    ///
+    /// Values are compressed with zstd on native, and with LZ4 on wasm, where it
+    /// helps values fit within the `localStorage` quota. Compression is flagged in
+    /// the stored value, so [`PkvStore::get`] decompresses it transparently, also
+    /// when compression is disabled again. Values that don't get any smaller are
+    /// stored as they are. Available with the `compression` feature.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::PkvStore;
+    /// let store = PkvStore::new("FooCompany", "BarGame").with_compression(4096);
+    /// ```
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, threshold: usize) -> Self {
+        self.compress_above = Some(threshold);
+        self
+    }
+
+    /// Encrypt values before they reach the backend
+    ///
+    /// Values that can't be decrypted, because they were written with another key,
+    /// without encryption, or were tampered with, are reported as
+    /// [`GetError::Decryption`]. Values already in the store aren't converted.
+    /// Available with the `encryption` feature.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::{Encryption, PkvStore};
+    /// # let key = [0; 32];
+    /// let store = PkvStore::new("FooCompany", "BarGame")
+    ///     .with_encryption(Encryption::new(key).with_hashed_keys());
+    /// ```
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.backend = Arc::new(encryption::EncryptedBackend::new(self.backend, encryption));
+        self
+    }
+
+    /// Compresses the serialized value if it's large enough, returning the bytes to
+    /// store and how they are encoded
+    fn pack<'a>(&self, payload: &'a [u8]) -> (Cow<'a, [u8]>, Encoding) {
+        let encoding = Encoding {
+            codec: self.codec.id(),
+            struct_map: self.codec.struct_map(),
+            schema_version: self.schema_version,
+            compression: None,
+        };
+        match compression::compress(payload, self.compress_above) {
+            Some((compressed, compression)) => (
+                Cow::Owned(compressed),
+                Encoding {
+                    compression: Some(compression),
+                    ..encoding
+                },
+            ),
+            None => (Cow::Borrowed(payload), encoding),
+        }
     }
 
     /// Serialize and store the value
     pub fn set<T: Serialize>(&mut self, key: impl AsRef<str>, value: &T) -> Result<(), SetError> {
-        self.inner.set(key.as_ref(), value)
+        let payload = self.codec.serialize(value)?;
+        let (payload, encoding) = self.pack(&payload);
+        let entry = Entry::written(&payload, encoding, None, None);
+        self.write(key.as_ref(), entry)
+    }
+
+    fn write(&mut self, key: &str, entry: Entry) -> Result<(), SetError> {
+        let bytes = stamp_value(&*self.backend, key, entry)?;
+        self.backend.set(key, &bytes)?;
+        self.watchers.notify(key, ChangeKind::Set);
+        Ok(())
     }
 
     /// More or less the same as set::<String>, but can take a &str
     pub fn set_string(&mut self, key: impl AsRef<str>, value: &str) -> Result<(), SetError> {
-        self.inner.set_string(key.as_ref(), value)
+        self.set(key, &value)
+    }
+
+    /// Serialize and store the value, which expires after `ttl`
+    ///
+    /// See [`PkvStore::set_expiring_at`].
+    pub fn set_with_ttl<T: Serialize>(
+        &mut self,
+        key: impl AsRef<str>,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<(), SetError> {
+        self.set_expiring_at(key, value, envelope::now() + ttl)
+    }
+
+    /// Serialize and store the value, which expires at `expires_at`
+    ///
+    /// Expired values are treated as missing, [`PkvStore::get`] returns
+    /// `Err(GetError::NotFound)` for them, and they are skipped by [`PkvStore::scan_prefix`]
+    /// and [`PkvStore::range`]. They are still stored, and counted by [`PkvStore::keys`]
+    /// and [`PkvStore::len`], until they are overwritten or removed with
+    /// [`PkvStore::purge_expired`].
+    ///
+    /// [`PkvStore::update`] keeps the expiry time, while [`PkvStore::set`] replaces
+    /// the value with one that doesn't expire.
+    pub fn set_expiring_at<T: Serialize>(
+        &mut self,
+        key: impl AsRef<str>,
+        value: &T,
+        expires_at: SystemTime,
+    ) -> Result<(), SetError> {
+        let payload = self.codec.serialize(value)?;
+        let (payload, encoding) = self.pack(&payload);
+        self.write(
+            key.as_ref(),
+            Entry::written(&payload, encoding, Some(expires_at), None),
+        )
     }
 
     /// Get the value for the given key
     /// returns Err(GetError::NotFound) if the key does not exist in the key value store.
     pub fn get<T: DeserializeOwned>(&self, key: impl AsRef<str>) -> Result<T, GetError> {
-        self.inner.get(key.as_ref())
+        read_value(&*self.backend, &*self.codec, key.as_ref())
+    }
+
+    /// Store the bytes as they are, without serializing them
+    ///
+    /// Use this for data that is already serialized, or binary data like images.
+    /// Raw values are flagged as such, so they should be read back with
+    /// [`PkvStore::get_raw`] rather than [`PkvStore::get`].
+    /// On wasm, they are stored base64 encoded.
+    pub fn set_raw(&mut self, key: impl AsRef<str>, value: &[u8]) -> Result<(), SetError> {
+        let (value, encoding) = self.pack(value);
+        let entry = Entry {
+            raw: true,
+            ..Entry::written(&value, encoding, None, None)
+        };
+        self.write(key.as_ref(), entry)
+    }
+
+    /// Get the bytes stored with [`PkvStore::set_raw`] for the given key
+    /// returns Err(GetError::NotFound) if the key does not exist in the key value store,
+    /// and Err(GetError::NotRaw) if the value was stored with [`PkvStore::set`].
+    pub fn get_raw(&self, key: impl AsRef<str>) -> Result<Vec<u8>, GetError> {
+        let bytes = self.backend.get(key.as_ref())?.ok_or(GetError::NotFound)?;
+        let entry = Entry::parse(&bytes)?;
+        if entry.is_expired() {
+            Err(GetError::NotFound)
+        } else if entry.raw {
+            Ok(entry.decompressed()?.into_owned())
+        } else {
+            Err(GetError::NotRaw)
+        }
     }
+
+    /// Get the value for the given typed key
+    ///
+    /// Like [`PkvStore::get`], but the value type is inferred from the key.
+    pub fn get_typed<T: DeserializeOwned>(&self, key: PkvKey<T>) -> Result<T, GetError> {
+        self.get(key.as_str())
+    }
+
+    /// Serialize and store the value for the given typed key
+    ///
+    /// Like [`PkvStore::set`], but the value must match the type of the key.
+    pub fn set_typed<T: Serialize>(&mut self, key: PkvKey<T>, value: &T) -> Result<(), SetError> {
+        self.set(key.as_str(), value)
+    }
+
+    /// Remove the value from the store for the given typed key
+    pub fn remove_typed<T>(&mut self, key: PkvKey<T>) -> Result<(), RemoveError> {
+        self.remove(key.as_str())
+    }
+
+    /// Returns `true` if the store contains a value for the given key
+    ///
+    /// Unlike [`PkvStore::get`], this doesn't need to know the type of the value,
+    /// and doesn't deserialize it. Missing keys are checked without reading any value,
+    /// existing ones are read to check whether they have expired.
+    pub fn contains_key(&self, key: impl AsRef<str>) -> Result<bool, GetError> {
+        let key = key.as_ref();
+        if !self.backend.contains_key(key)? {
+            return Ok(false);
+        }
+        match self.backend.get(key)? {
+            Some(bytes) => Ok(Entry::parse(&bytes)?.live().is_some()),
+            // removed in the meantime
+            None => Ok(false),
+        }
+    }
+
+    /// Get the metadata of the value for the given key
+    /// returns Err(GetError::NotFound) if the key does not exist in the key value store.
+    ///
+    /// The timestamps are recorded whenever a value is written, e.g. with
+    /// [`PkvStore::set`], and don't need the type of the value. Keeping the creation
+    /// time means every write reads the current value first, and decrypts it if
+    /// encryption, see `PkvStore::with_encryption`, is enabled. The read isn't part of the
+    /// write, so if another process writes the key in between, the creation time
+    /// may be reset to the time of the later write.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::PkvStore;
+    /// let store = PkvStore::new("FooCompany", "BarGame");
+    /// let metadata = store.metadata("save_slot_1")?;
+    /// println!("saved at {:?}, {} bytes", metadata.modified, metadata.encoded_len);
+    /// # Ok::<(), bevy_pkv::GetError>(())
+    /// ```
+    pub fn metadata(&self, key: impl AsRef<str>) -> Result<EntryMetadata, GetError> {
+        let bytes = self.backend.get(key.as_ref())?.ok_or(GetError::NotFound)?;
+        let entry = Entry::parse(&bytes)?;
+        if entry.is_expired() {
+            return Err(GetError::NotFound);
+        }
+        Ok(entry.into())
+    }
+
+    /// Get the value for the given key, or insert the value returned by `f` if there is none
+    ///
+    /// `f` is only called, and its value only inserted, when the key doesn't exist.
+    /// If the existing value fails to deserialize, the error is returned and
+    /// the value is left untouched.
+    ///
+    /// The insert is a [`PkvStore::compare_and_swap`], so if another writer inserts
+    /// a value first, that value is returned instead.
+    pub fn get_or_insert_with<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: impl AsRef<str>,
+        f: impl FnOnce() -> T,
+    ) -> Result<T, UpdateError> {
+        let key = key.as_ref();
+        match self.get(key) {
+            Err(GetError::NotFound) => {}
+            value => return Ok(value?),
+        }
+        let value = f();
+        match self.compare_and_swap(key, None, Some(&value)) {
+            Ok(()) => Ok(value),
+            Err(CasError::Mismatch {
+                current: Some(current),
+            }) => Ok(current),
+            Err(CasError::Mismatch { current: None }) => {
+                unreachable!("compare_and_swap expecting no value failed with no value")
+            }
+            Err(CasError::Get(err)) => Err(err.into()),
+            Err(CasError::Set(err)) => Err(err.into()),
+        }
+    }
+
+    /// Atomically read, modify and write the value for the given key
+    ///
+    /// `f` is given the current value, or `None` if there is none, and returns
+    /// the new value. Returning `None` removes the key. The new value is returned.
+    ///
+    /// The new value is written with a compare and swap, so no other write can sneak
+    /// in between. If the value is concurrently modified, `f` is called again with
+    /// the new current value.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::PkvStore;
+    /// let mut store = PkvStore::new("FooCompany", "BarGame");
+    /// let play_count = store.update::<u32>("play_count", |count| Some(count.unwrap_or(0) + 1))?;
+    /// # Ok::<(), bevy_pkv::UpdateError>(())
+    /// ```
+    pub fn update<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: impl AsRef<str>,
+        mut f: impl FnMut(Option<T>) -> Option<T>,
+    ) -> Result<Option<T>, UpdateError> {
+        let key = key.as_ref();
+        let mut current = self.backend.get(key)?;
+        loop {
+            let entry = current.as_deref().map(Entry::parse).transpose()?;
+            let entry = entry.filter(|entry| !entry.is_expired());
+            let expires_at = entry.and_then(|entry| entry.expires_at);
+            let created = entry.and_then(|entry| entry.created);
+            let value = entry.map(|entry| decode_entry(&*self.codec, entry));
+            let value = f(value.transpose()?);
+            let new = value.as_ref().map(|value| self.codec.serialize(value));
+            let new = new.transpose()?;
+            let new = new.map(|new| {
+                let (new, encoding) = self.pack(&new);
+                Entry::written(&new, encoding, expires_at, created).to_bytes()
+            });
+            match self
+                .backend
+                .compare_and_swap(key, current.as_deref(), new.as_deref())
+            {
+                Ok(()) => {
+                    self.notify_swapped(key, current.is_some(), new.is_some());
+                    return Ok(value);
+                }
+                Err(CasError::Mismatch { current: actual }) => current = actual,
+                Err(CasError::Get(err)) => return Err(err.into()),
+                Err(CasError::Set(err)) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Replace the value for the given key with `new`, but only if the current value is `expected`
+    ///
+    /// `None` as the expected value means the key must not exist, or has expired, and
+    /// `None` as the new value removes the key. Values are compared by their serialized
+    /// bytes. The new value doesn't expire.
+    ///
+    /// If the current value doesn't match, nothing is written and
+    /// [`CasError::Mismatch`] is returned with the current value, so the caller
+    /// can retry based on it. A current value stored with [`PkvStore::set_raw`], or
+    /// with another codec, is an error rather than a match.
+    pub fn compare_and_swap<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: impl AsRef<str>,
+        expected: Option<&T>,
+        new: Option<&T>,
+    ) -> Result<(), CasError<T>> {
+        let expected = expected
+            .map(|value| self.codec.serialize(value))
+            .transpose()?;
+        let new_payload = new.map(|value| self.codec.serialize(value)).transpose()?;
+        let key = key.as_ref();
+        // the stored bytes may have a header, so compare the payloads and then swap
+        // based on the stored bytes, retrying if they changed in between
+        let mut current = self.backend.get(key)?;
+        loop {
+            let entry = current.as_deref().map(Entry::parse).transpose()?;
+            let entry = entry.filter(|entry| !entry.is_expired());
+            // raw values and values from another codec can't match, even if the bytes do
+            if let Some(entry) = entry {
+                check_decodable(&*self.codec, entry)?;
+            }
+            let payload = entry.map(|entry| entry.decompressed()).transpose()?;
+            if payload.as_deref() != expected.as_deref() {
+                let current = entry.map(|entry| decode_entry(&*self.codec, entry));
+                let current = current.transpose()?;
+                return Err(CasError::Mismatch { current });
+            }
+            let created = entry.and_then(|entry| entry.created);
+            let new = new_payload.as_deref().map(|new| {
+                let (new, encoding) = self.pack(new);
+                Entry::written(&new, encoding, None, created).to_bytes()
+            });
+            match self
+                .backend
+                .compare_and_swap(key, current.as_deref(), new.as_deref())
+            {
+                Ok(()) => {
+                    self.notify_swapped(key, current.is_some(), new.is_some());
+                    return Ok(());
+                }
+                Err(CasError::Mismatch { current: actual }) => current = actual,
+                Err(CasError::Get(err)) => return Err(err.into()),
+                Err(CasError::Set(err)) => return Err(err.into()),
+            }
+        }
+    }
+
     /// Remove the value from the store for the given key
     /// returns the removed value if one existed
+    ///
+    /// The value is removed even if it fails to deserialize, or has expired.
     pub fn remove_and_get<T: DeserializeOwned>(
         &mut self,
         key: impl AsRef<str>,
     ) -> Result<Option<T>, RemoveError> {
-        self.inner.remove_and_get(key.as_ref())
+        let bytes = match self.backend.remove(key.as_ref()) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return Ok(None),
+            // the value is removed even though it couldn't be decrypted
+            Err(RemoveError::Decryption) => {
+                self.watchers.notify(key.as_ref(), ChangeKind::Removed);
+                return Err(RemoveError::Decryption);
+            }
+            Err(err) => return Err(err),
+        };
+        self.watchers.notify(key.as_ref(), ChangeKind::Removed);
+        let value = Entry::parse(&bytes).and_then(|entry| {
+            let entry = (!entry.is_expired()).then_some(entry);
+            entry
+                .map(|entry| decode_entry(&*self.codec, entry))
+                .transpose()
+        });
+        match value {
+            Ok(value) => Ok(value),
+            Err(GetError::Deserialization(err)) => Err(RemoveError::Deserialization(err)),
+            Err(GetError::Decryption) => Err(RemoveError::Decryption),
+            Err(GetError::Corruption(err)) => Err(RemoveError::Corruption(err)),
+            Err(err) => Err(RemoveError::Backend(err.into())),
+        }
     }
 
     /// Remove the value from the store for the given key
+    ///
+    /// The value isn't read, so this works even if it can't be decrypted.
     pub fn remove(&mut self, key: impl AsRef<str>) -> Result<(), RemoveError> {
-        self.inner.remove(key.as_ref())
+        if self.backend.delete(key.as_ref())? {
+            self.watchers.notify(key.as_ref(), ChangeKind::Removed);
+        }
+        Ok(())
     }
 
     /// Clear all key values data
     /// returns Err(SetError) if clear error
     pub fn clear(&mut self) -> Result<(), SetError> {
-        self.inner.clear()
+        self.backend.clear()?;
+        self.watchers.notify("", ChangeKind::Cleared);
+        Ok(())
+    }
+
+    /// Remove all expired values from the store, returning how many were removed
+    ///
+    /// Expired values are already treated as missing, this frees up the space they take.
+    /// It reads every value in the store, so it's best called occasionally,
+    /// e.g. on startup.
+    pub fn purge_expired(&mut self) -> Result<usize, SetError> {
+        let mut expired = Vec::new();
+        for entry in self.backend.range((Bound::Unbounded, Bound::Unbounded)) {
+            let (key, bytes) = entry.map_err(backend::get_to_set)?;
+            // values with a broken header are left for `get` to report
+            if Entry::parse(&bytes).is_ok_and(|entry| entry.is_expired()) {
+                expired.push((key, bytes));
+            }
+        }
+        let mut purged = 0;
+        for (key, bytes) in expired {
+            // only remove the value if it hasn't been replaced in the meantime
+            match self.backend.compare_and_swap(&key, Some(&bytes), None) {
+                Ok(()) => {
+                    self.watchers.notify(&key, ChangeKind::Removed);
+                    purged += 1;
+                }
+                Err(CasError::Mismatch { .. }) => {}
+                Err(CasError::Get(err)) => return Err(backend::get_to_set(err)),
+                Err(CasError::Set(err)) => return Err(err),
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Subscribe to changes of the given key, or of all keys starting with it
+    ///
+    /// Changes made through this store with `set`, `set_string`, `remove`,
+    /// `remove_and_get`, `clear` and the other writing methods are sent to the
+    /// returned receiver, which can be polled with [`Receiver::try_recv`], e.g. from
+    /// a bevy system. Values that expire aren't reported until they're purged.
+    /// Dropping the receiver ends the subscription.
+    ///
+    /// On sled, sets and removals are reported by sled's own `watch_prefix`, so they
+    /// arrive asynchronously, and clearing the store reports a removal for each key
+    /// as well as the [`ChangeKind::Cleared`] event. They are forwarded by a thread
+    /// per subscription, which ends on the first change after the receiver is dropped,
+    /// or when the store is dropped.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::{ChangeKind, PkvStore};
+    /// let mut store = PkvStore::new("FooCompany", "BarGame");
+    /// let settings = store.watch("settings/");
+    /// store.set("settings/volume", &0.5)?;
+    /// for changed in settings.try_iter() {
+    ///     if changed.kind == ChangeKind::Set {
+    ///         println!("{} changed", changed.key);
+    ///     }
+    /// }
+    /// # Ok::<(), bevy_pkv::SetError>(())
+    /// ```
+    pub fn watch(&self, key_or_prefix: impl AsRef<str>) -> Receiver<Changed> {
+        let prefix = key_or_prefix.as_ref();
+        let (sender, receiver) = mpsc::channel();
+        let native = self.backend.watch_prefix(prefix, sender.clone());
+        self.watchers.add(prefix, sender, native);
+        receiver
+    }
+
+    fn notify_swapped(&self, key: &str, existed: bool, exists: bool) {
+        match (existed, exists) {
+            (_, true) => self.watchers.notify(key, ChangeKind::Set),
+            (true, false) => self.watchers.notify(key, ChangeKind::Removed),
+            (false, false) => {}
+        }
+    }
+
+    /// Get a view of the store where all keys are prefixed with the given namespace
+    ///
+    /// See [`Namespace`].
+    pub fn namespace(&mut self, name: &str) -> Namespace<'_> {
+        Namespace::new(self, name)
+    }
+
+    /// Start a batch of writes that are committed together
+    ///
+    /// See [`WriteBatch`] for the atomicity guarantees of each backend.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::PkvStore;
+    /// let mut store = PkvStore::new("FooCompany", "BarGame");
+    /// let mut batch = store.batch();
+    /// batch.set("level", &3)?.set_string("checkpoint", "castle")?;
+    /// batch.remove("boss_defeated");
+    /// batch.commit()?;
+    /// # Ok::<(), bevy_pkv::SetError>(())
+    /// ```
+    pub fn batch(&mut self) -> WriteBatch<'_> {
+        WriteBatch::new(self)
+    }
+
+    /// Iterate over all keys in the store
+    ///
+    /// Keys are yielded in the order of the underlying backend, which is sorted
+    /// for the native backends.
+    pub fn keys(&self) -> impl Iterator<Item = Result<String, GetError>> + '_ {
+        self.backend.keys()
+    }
+
+    /// Returns the number of keys in the store
+    pub fn len(&self) -> Result<usize, GetError> {
+        self.backend.len()
+    }
+
+    /// Returns `true` if the store contains no keys
+    pub fn is_empty(&self) -> Result<bool, GetError> {
+        self.backend.is_empty()
+    }
+
+    /// Iterate over all key value pairs where the key starts with `prefix`, in key order
+    ///
+    /// Values are deserialized lazily, so a value that fails to deserialize only
+    /// results in an error for that entry. Expired values are skipped. The outer error is returned if reading
+    /// from the store itself fails.
+    pub fn scan_prefix<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+        prefix: impl AsRef<str>,
+    ) -> impl Iterator<Item = Result<(String, Result<T, GetError>), GetError>> + 'a {
+        self.decode_entries(self.backend.scan_prefix(prefix.as_ref()))
+    }
+
+    /// Iterate over all key value pairs with keys within `range`, in key order
+    ///
+    /// Like [`PkvStore::scan_prefix`], values are deserialized lazily.
+    pub fn range<'a, 'r, T: DeserializeOwned + 'a>(
+        &'a self,
+        range: impl RangeBounds<&'r str>,
+    ) -> impl Iterator<Item = Result<(String, Result<T, GetError>), GetError>> + 'a {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.decode_entries(self.backend.range(range))
+    }
+
+    fn decode_entries<'a, T: DeserializeOwned + 'a>(
+        &self,
+        entries: BackendEntries<'a>,
+    ) -> Entries<'a, T> {
+        let codec = self.codec.clone();
+        Box::new(entries.filter_map(move |entry| {
+            let (key, bytes) = match entry {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            match Entry::parse(&bytes) {
+                Ok(entry) if entry.is_expired() => None,
+                Ok(entry) => Some(Ok((key, decode_entry(&*codec, entry)))),
+                Err(err) => Some(Ok((key, Err(err)))),
+            }
+        }))
     }
 }
 
@@ -191,6 +1046,17 @@ mod tests {
         console_error_panic_hook::set_once();
     }
 
+    /// Runs the test against a store in memory, and against the default backend in a
+    /// temporary directory, so the overrides of each backend are covered too
+    fn for_each_store(test: impl Fn(PkvStore)) {
+        test(PkvStore::in_memory());
+        #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+        {
+            let dir = tempfile::tempdir().expect("failed to create temp dir");
+            test(PkvStore::new_in_dir(dir.path()));
+        }
+    }
+
     #[test]
     fn set_string() {
         setup();
@@ -236,6 +1102,82 @@ mod tests {
         assert!(matches!(err, GetError::NotFound));
     }
 
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    #[test]
+    fn try_new_in_dir_not_a_dir() {
+        use crate::OpenError;
+
+        setup();
+
+        let file = tempfile::NamedTempFile::new().expect("failed to create temp file");
+        let err = PkvStore::try_new_in_dir(file.path()).unwrap_err();
+        assert!(matches!(err, OpenError::Io(_)));
+    }
+
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    #[test]
+    fn try_new_in_dir_locked() {
+        use crate::OpenError;
+
+        setup();
+
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let _store = PkvStore::try_new_in_dir(dir.path()).unwrap();
+        let err = PkvStore::try_new_in_dir(dir.path()).unwrap_err();
+        let OpenError::Locked { path, .. } = err else {
+            panic!("{err:?}");
+        };
+        assert!(path.starts_with(dir.path()), "{path:?}");
+        assert!(path.exists());
+    }
+
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    #[test]
+    fn lock_policy() {
+        use crate::{LockPolicy, OpenError};
+        use std::time::Duration;
+
+        setup();
+
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut store = PkvStore::try_new_in_dir(dir.path()).unwrap();
+        store.set_string("name", "alice").unwrap();
+
+        let policy = LockPolicy::Retry {
+            timeout: Duration::from_millis(100),
+        };
+        let err = PkvStore::try_new_in_dir_with_lock_policy(dir.path(), policy).unwrap_err();
+        assert!(matches!(err, OpenError::Locked { .. }), "{err:?}");
+
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            drop(store);
+        });
+        let policy = LockPolicy::Retry {
+            timeout: Duration::from_secs(10),
+        };
+        let store = PkvStore::try_new_in_dir_with_lock_policy(dir.path(), policy).unwrap();
+        release.join().unwrap();
+        assert_eq!(store.get::<String>("name").unwrap(), "alice");
+        drop(store);
+
+        // redb allows several readers, so falling back to read-only works while the
+        // store is open read-only elsewhere
+        #[cfg(redb_backend)]
+        {
+            let _reader = PkvStore::open_read_only_in_dir(dir.path()).unwrap();
+            let policy = LockPolicy::ReadOnly {
+                timeout: Duration::ZERO,
+            };
+            let mut store = PkvStore::try_new_in_dir_with_lock_policy(dir.path(), policy).unwrap();
+            assert_eq!(store.get::<String>("name").unwrap(), "alice");
+            assert!(matches!(
+                store.set_string("name", "bob"),
+                Err(crate::SetError::ReadOnly)
+            ));
+        }
+    }
+
     #[test]
     fn clear() {
         setup();
@@ -302,4 +1244,774 @@ mod tests {
         assert_eq!(user, removed_user);
         assert_eq!(store.get::<User>("user").ok(), None);
     }
+
+    #[test]
+    fn keys() {
+        setup();
+        for_each_store(|mut store| {
+            assert!(store.is_empty().unwrap());
+
+            store.set_string("b", "bar").unwrap();
+            store.set_string("a", "foo").unwrap();
+
+            let mut keys = store.keys().collect::<Result<Vec<_>, _>>().unwrap();
+            keys.sort();
+            assert_eq!(keys, ["a", "b"]);
+            assert_eq!(store.len().unwrap(), 2);
+            assert!(!store.is_empty().unwrap());
+        });
+    }
+
+    #[test]
+    fn scan_prefix() {
+        setup();
+        for_each_store(|mut store| {
+            store.set("level/02", &2).unwrap();
+            store.set("level/01", &1).unwrap();
+            store.set("levels", &0).unwrap();
+            store.set("settings", &3).unwrap();
+
+            let levels = store
+                .scan_prefix::<u32>("level/")
+                .map(|entry| {
+                    let (key, value) = entry.unwrap();
+                    (key, value.unwrap())
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                levels,
+                [("level/01".to_string(), 1), ("level/02".to_string(), 2)]
+            );
+        });
+    }
+
+    #[test]
+    fn range() {
+        setup();
+        for_each_store(|mut store| {
+            for i in 0..5 {
+                store.set(format!("level/{i:02}"), &i).unwrap();
+            }
+            store.set_string("level/name", "not a number").unwrap();
+
+            let keys = store
+                .range::<u32>("level/01".."level/03")
+                .map(|entry| entry.unwrap().0)
+                .collect::<Vec<_>>();
+            assert_eq!(keys, ["level/01", "level/02"]);
+
+            let last = store.range::<u32>("level/04"..).collect::<Vec<_>>();
+            assert_eq!(last.len(), 2);
+            let (key, value) = last[0].as_ref().unwrap();
+            assert_eq!((key.as_str(), value.as_ref().unwrap()), ("level/04", &4));
+            // values are deserialized per entry
+            assert!(last[1].as_ref().unwrap().1.is_err());
+        });
+    }
+
+    #[test]
+    fn contains_key() {
+        setup();
+        for_each_store(|mut store| {
+            assert!(!store.contains_key("user").unwrap());
+
+            let user = User {
+                name: "alice".to_string(),
+                age: 32,
+            };
+            store.set("user", &user).unwrap();
+            assert!(store.contains_key("user").unwrap());
+
+            store.remove("user").unwrap();
+            assert!(!store.contains_key("user").unwrap());
+        });
+    }
+
+    #[test]
+    fn batch() {
+        setup();
+        for_each_store(|mut store| {
+            store.set_string("stale", "old").unwrap();
+            store.set_string("removed", "old").unwrap();
+
+            let mut batch = store.batch();
+            batch.set_string("stale", "new").unwrap();
+            batch.remove("removed");
+            batch.set("level", &3).unwrap();
+            batch.commit().unwrap();
+
+            assert_eq!(store.get::<String>("stale").unwrap(), "new");
+            assert!(!store.contains_key("removed").unwrap());
+            assert_eq!(store.get::<u32>("level").unwrap(), 3);
+
+            let mut batch = store.batch();
+            batch.set_string("before_clear", "gone").unwrap();
+            batch.clear();
+            batch.set_string("after_clear", "kept").unwrap();
+            batch.commit().unwrap();
+
+            let keys = store.keys().collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(keys, ["after_clear"]);
+        });
+    }
+
+    #[test]
+    fn update() {
+        setup();
+        for_each_store(|mut store| {
+            let count = store.update::<u32>("count", |count| Some(count.unwrap_or(0) + 1));
+            assert_eq!(count.unwrap(), Some(1));
+            let count = store.update::<u32>("count", |count| Some(count.unwrap_or(0) + 1));
+            assert_eq!(count.unwrap(), Some(2));
+            assert_eq!(store.get::<u32>("count").unwrap(), 2);
+
+            let removed = store.update::<u32>("count", |_| None).unwrap();
+            assert_eq!(removed, None);
+            assert!(!store.contains_key("count").unwrap());
+        });
+    }
+
+    #[test]
+    fn update_deserialize_error() {
+        use crate::UpdateError;
+
+        setup();
+        let mut store = PkvStore::in_memory();
+        store.set_string("name", "alice").unwrap();
+
+        let err = store.update::<u32>("name", |_| Some(1)).unwrap_err();
+        assert!(matches!(err, UpdateError::Get(_)));
+        assert_eq!(store.get::<String>("name").unwrap(), "alice");
+    }
+
+    #[cfg(redb_backend)]
+    #[test]
+    fn open_redb() {
+        setup();
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut store = PkvStore::open_redb(dir.path()).unwrap();
+        store.set_string("hello", "redb").unwrap();
+        assert_eq!(store.get::<String>("hello").unwrap(), "redb");
+        assert!(dir.path().join("bevy_pkv.redb").exists());
+    }
+
+    #[cfg(sled_backend)]
+    #[test]
+    fn open_sled() {
+        setup();
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut store = PkvStore::open_sled(dir.path()).unwrap();
+        store.set_string("hello", "sled").unwrap();
+        assert_eq!(store.get::<String>("hello").unwrap(), "sled");
+        assert!(dir.path().join("bevy_pkv.sled").exists());
+    }
+
+    #[cfg(rocksdb_backend)]
+    #[test]
+    fn open_rocksdb() {
+        setup();
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut store = PkvStore::open_rocksdb(dir.path()).unwrap();
+        store.set_string("hello", "rocksdb").unwrap();
+        assert_eq!(store.get::<String>("hello").unwrap(), "rocksdb");
+        assert!(dir.path().join("bevy_rocksdb_pkv").exists());
+    }
+
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    #[test]
+    fn open_read_only() {
+        use crate::{ErrorKind, OpenError, RemoveError, SetError};
+
+        setup();
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+
+        let missing = dir.path().join("missing");
+        let err = PkvStore::open_read_only_in_dir(&missing).unwrap_err();
+        assert!(matches!(err, OpenError::Io(_)));
+        assert!(!missing.exists());
+
+        let mut store = PkvStore::new_in_dir(dir.path());
+        store.set_string("name", "alice").unwrap();
+        drop(store);
+
+        let mut store = PkvStore::open_read_only_in_dir(dir.path()).unwrap();
+        assert_eq!(store.get::<String>("name").unwrap(), "alice");
+        let err = store.set_string("name", "bob").unwrap_err();
+        assert!(matches!(err, SetError::ReadOnly));
+        assert_eq!(err.kind(), ErrorKind::ReadOnly);
+        assert!(matches!(store.remove("name"), Err(RemoveError::ReadOnly)));
+        assert!(matches!(store.clear(), Err(SetError::ReadOnly)));
+        assert!(store.update::<String>("name", |_| None).is_err());
+        assert_eq!(store.get::<String>("name").unwrap(), "alice");
+    }
+
+    #[test]
+    fn ttl() {
+        use crate::GetError;
+        use std::time::{Duration, SystemTime};
+
+        setup();
+        for_each_store(|mut store| {
+            store
+                .set_with_ttl("seed", &42u32, Duration::from_secs(3600))
+                .unwrap();
+            store
+                .set_expiring_at("news", &"old news", SystemTime::UNIX_EPOCH)
+                .unwrap();
+            store
+                .set_expiring_at("challenge", &1u32, SystemTime::UNIX_EPOCH)
+                .unwrap();
+            store.set_string("name", "alice").unwrap();
+
+            assert_eq!(store.get::<u32>("seed").unwrap(), 42);
+            assert!(matches!(
+                store.get::<String>("news"),
+                Err(GetError::NotFound)
+            ));
+            assert!(!store.contains_key("news").unwrap());
+            assert_eq!(store.scan_prefix::<String>("news").count(), 0);
+
+            // updates keep the expiry time, unless the value had already expired
+            let seed = store.update::<u32>("seed", |seed| seed.map(|seed| seed + 1));
+            assert_eq!(seed.unwrap(), Some(43));
+            assert_eq!(store.get::<u32>("seed").unwrap(), 43);
+            assert_eq!(store.get_or_insert_with("challenge", || 2u32).unwrap(), 2);
+            assert_eq!(store.get::<u32>("challenge").unwrap(), 2);
+
+            assert_eq!(store.len().unwrap(), 4);
+            assert_eq!(store.purge_expired().unwrap(), 1);
+            assert_eq!(store.len().unwrap(), 3);
+            assert_eq!(store.purge_expired().unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn codecs() {
+        use crate::Codec;
+
+        fn round_trip(codec: impl Codec + 'static) {
+            let mut store = PkvStore::in_memory().with_codec(codec);
+            let user = User {
+                name: "alice".to_string(),
+                age: 32,
+            };
+            store.set("user", &user).unwrap();
+            assert_eq!(store.get::<User>("user").unwrap(), user);
+            assert_eq!(store.scan_prefix::<User>("u").count(), 1);
+
+            store.set_raw("raw", &[0xc1, 0]).unwrap();
+            assert_eq!(store.get_raw("raw").unwrap(), [0xc1, 0]);
+            assert!(store.get::<u16>("raw").is_err());
+        }
+
+        setup();
+        round_trip(crate::MessagePackCodec);
+        #[cfg(any(feature = "json", wasm))]
+        round_trip(crate::JsonCodec);
+        #[cfg(feature = "ron")]
+        round_trip(crate::RonCodec);
+        #[cfg(feature = "postcard")]
+        round_trip(crate::PostcardCodec);
+    }
+
+    #[test]
+    fn metadata() {
+        use std::time::Duration;
+
+        setup();
+        let mut store = PkvStore::in_memory();
+        store.set_string("name", "alice").unwrap();
+        let first = store.metadata("name").unwrap();
+        assert!(first.created.is_some());
+        assert_eq!(first.created, first.modified);
+        assert_eq!(first.encoded_len, rmp_serde::to_vec("alice").unwrap().len());
+
+        std::thread::sleep(Duration::from_millis(5));
+        store.set_string("name", "bob").unwrap();
+        let second = store.metadata("name").unwrap();
+        assert_eq!(second.created, first.created);
+        assert!(second.modified > first.modified);
+        assert_eq!(store.get::<String>("name").unwrap(), "bob");
+
+        // values written before metadata was recorded have no timestamps
+        let legacy = rmp_serde::to_vec("carol").unwrap();
+        store.backend.set("legacy", &legacy).unwrap();
+        let metadata = store.metadata("legacy").unwrap();
+        assert_eq!(metadata.created, None);
+        assert_eq!(metadata.encoded_len, legacy.len());
+
+        assert!(matches!(
+            store.metadata("missing"),
+            Err(crate::GetError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn value_header() {
+        use crate::{Codec, DecodeFn, MessagePackCodec};
+        use std::error::Error;
+
+        /// MessagePack with structs as arrays, under another id
+        #[derive(Debug)]
+        struct Compact;
+
+        impl Codec for Compact {
+            fn id(&self) -> u8 {
+                100
+            }
+
+            fn encode(
+                &self,
+                value: &dyn erased_serde::Serialize,
+            ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+                Ok(rmp_serde::to_vec(value)?)
+            }
+
+            fn decode(
+                &self,
+                bytes: &[u8],
+                decode: DecodeFn<'_>,
+            ) -> Result<(), Box<dyn Error + Send + Sync>> {
+                MessagePackCodec.decode(bytes, decode)
+            }
+        }
+
+        setup();
+        let mut store = PkvStore::in_memory().with_schema_version(3);
+        store.set_string("name", "alice").unwrap();
+        let metadata = store.metadata("name").unwrap();
+        assert_eq!(metadata.codec_id, Some(1));
+        assert_eq!(metadata.struct_map, Some(true));
+        assert_eq!(metadata.schema_version, Some(3));
+
+        // values written with another codec aren't decoded
+        let mut store = store.with_codec(Compact);
+        assert!(matches!(
+            store.get::<String>("name"),
+            Err(crate::GetError::Deserialization(_))
+        ));
+        store.set_string("name", "bob").unwrap();
+        let metadata = store.metadata("name").unwrap();
+        assert_eq!(metadata.codec_id, Some(100));
+        assert_eq!(metadata.struct_map, Some(false));
+        assert_eq!(store.get::<String>("name").unwrap(), "bob");
+
+        // values written before the header existed are decoded with the store's codec
+        let legacy = rmp_serde::to_vec("carol").unwrap();
+        store.backend.set("legacy", &legacy).unwrap();
+        assert_eq!(store.get::<String>("legacy").unwrap(), "carol");
+        assert_eq!(store.metadata("legacy").unwrap().codec_id, None);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression() {
+        setup();
+        let mut store = PkvStore::in_memory().with_compression(64);
+        let save = "level 1 cleared, ".repeat(100);
+        store.set("save", &save).unwrap();
+        assert!(store.metadata("save").unwrap().encoded_len < save.len() / 2);
+        assert_eq!(store.get::<String>("save").unwrap(), save);
+
+        // small values are stored as they are
+        store.set_string("name", "alice").unwrap();
+        let encoded_len = rmp_serde::to_vec("alice").unwrap().len();
+        assert_eq!(store.metadata("name").unwrap().encoded_len, encoded_len);
+
+        store.set_raw("raw", save.as_bytes()).unwrap();
+        assert_eq!(store.get_raw("raw").unwrap(), save.as_bytes());
+
+        // values are compared uncompressed
+        let cleared = "all levels cleared".to_string();
+        store
+            .compare_and_swap("save", Some(&save), Some(&cleared))
+            .unwrap();
+
+        // a corrupted size isn't trusted
+        let lz4 = crate::compression::Compression::Lz4;
+        let compressed = lz4_flex::compress_prepend_size(save.as_bytes());
+        assert_eq!(lz4.decompress(&compressed).unwrap(), save.as_bytes());
+        let corrupted = [&u32::MAX.to_le_bytes(), &compressed[4..]].concat();
+        assert!(matches!(
+            lz4.decompress(&corrupted),
+            Err(crate::GetError::Corruption(_))
+        ));
+        #[cfg(not(wasm))]
+        {
+            let zstd = crate::compression::Compression::Zstd;
+            let zeros = vec![0; 1 << 22];
+            let compressed = zstd::bulk::compress(&zeros, 0).unwrap();
+            assert_eq!(zstd.decompress(&compressed).unwrap(), zeros);
+            // streamed frames don't record their size
+            let streamed = zstd::stream::encode_all(&zeros[..], 0).unwrap();
+            assert!(matches!(
+                zstd.decompress(&streamed),
+                Err(crate::GetError::Corruption(_))
+            ));
+        }
+
+        // compressed values can be read after compression is disabled again
+        store.set("save", &save).unwrap();
+        store.compress_above = None;
+        assert_eq!(store.get::<String>("save").unwrap(), save);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encryption() {
+        use crate::{Encryption, ErrorKind, GetError, RemoveError};
+
+        setup();
+        let mut plain = PkvStore::in_memory();
+        let backend = plain.backend.clone();
+        let encrypted = move |key, hash_keys| {
+            let mut store = PkvStore::in_memory();
+            store.backend = backend.clone();
+            let encryption = Encryption::new(key);
+            let encryption = match hash_keys {
+                true => encryption.with_hashed_keys(),
+                false => encryption,
+            };
+            store.with_encryption(encryption)
+        };
+
+        let mut store = encrypted([1; 32], false);
+        store.set_string("token", "secret").unwrap();
+        assert_eq!(store.get::<String>("token").unwrap(), "secret");
+        let stored = plain.backend.get("token").unwrap().unwrap();
+        assert!(!stored.windows(6).any(|bytes| bytes == b"secret"));
+
+        // a wrong key, tampering and unencrypted values are all detected
+        let err = encrypted([2; 32], false)
+            .get::<String>("token")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Decryption);
+        let mut tampered = stored.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        plain.backend.set("token", &tampered).unwrap();
+        assert!(matches!(
+            store.get::<String>("token"),
+            Err(GetError::Decryption)
+        ));
+        plain.set_string("plain", "alice").unwrap();
+        assert!(matches!(
+            store.get::<String>("plain"),
+            Err(GetError::Decryption)
+        ));
+
+        // values that can't be decrypted can still be overwritten
+        store.set_string("token", "secret").unwrap();
+        assert_eq!(store.get::<String>("token").unwrap(), "secret");
+
+        // and removed, which is reported to watchers
+        let changes = store.watch("plain");
+        store.remove("plain").unwrap();
+        assert!(!plain.contains_key("plain").unwrap());
+        plain.set_string("plain", "alice").unwrap();
+        assert!(matches!(
+            store.remove_and_get::<String>("plain"),
+            Err(RemoveError::Decryption)
+        ));
+        assert!(!plain.contains_key("plain").unwrap());
+        assert_eq!(changes.try_iter().count(), 2);
+        store.clear().unwrap();
+
+        let mut store = encrypted([1; 32], true);
+        store.set_string("save/1", "a").unwrap();
+        store.set_string("save/2", "b").unwrap();
+        store.set_string("token", "secret").unwrap();
+        assert!(!plain.contains_key("token").unwrap());
+        let keys = store.keys().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(keys, ["save/1", "save/2", "token"]);
+        let saves = store.scan_prefix::<String>("save/");
+        let saves = saves.map(|entry| entry.unwrap().1.unwrap());
+        assert_eq!(saves.collect::<Vec<_>>(), ["a", "b"]);
+        store
+            .update::<String>("token", |_| Some("renewed".to_string()))
+            .unwrap();
+        assert_eq!(store.get::<String>("token").unwrap(), "renewed");
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_api() {
+        use bevy::tasks::block_on;
+
+        setup();
+        let mut store = PkvStore::in_memory();
+
+        // operations run in order, so the get sees the set that wasn't awaited yet
+        let set = store.set_async("user", &"alice");
+        let user = store.get_async::<String>("user");
+        assert_eq!(block_on(user).unwrap(), "alice");
+        block_on(set).unwrap();
+        assert_eq!(store.get::<String>("user").unwrap(), "alice");
+
+        block_on(store.remove_async("user")).unwrap();
+        assert!(matches!(
+            block_on(store.get_async::<String>("user")),
+            Err(crate::GetError::NotFound)
+        ));
+    }
+
+    // uses the default backend, since sled reports changes itself
+    #[cfg(any(sled_backend, rocksdb_backend, redb_backend))]
+    #[test]
+    fn watch() {
+        use crate::{ChangeKind, Changed};
+        use std::time::Duration;
+
+        setup();
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut store = PkvStore::new_in_dir(dir.path());
+        let settings = store.watch("settings/");
+        let next = || settings.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        store.set("settings/volume", &0.5).unwrap();
+        store.set_string("name", "alice").unwrap();
+        store.remove("settings/volume").unwrap();
+        let changed = |key: &str, kind| Changed {
+            key: key.to_string(),
+            kind,
+        };
+        assert_eq!(next(), changed("settings/volume", ChangeKind::Set));
+        assert_eq!(next(), changed("settings/volume", ChangeKind::Removed));
+
+        store.set("settings/volume", &0.5).unwrap();
+        store.clear().unwrap();
+        // on sled, the removal of each key is also reported
+        while next().kind != ChangeKind::Cleared {}
+    }
+
+    #[test]
+    fn in_memory() {
+        setup();
+        let mut store = PkvStore::in_memory();
+        store.set_string("b", "two").unwrap();
+        store.set_string("a", "one").unwrap();
+        store.set("c", &3u32).unwrap();
+        assert_eq!(store.get::<String>("a").unwrap(), "one");
+        let keys: Vec<_> = store
+            .range::<String>("a"..="b")
+            .map(|e| e.unwrap().0)
+            .collect();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(store.range::<String>("b".."a").count(), 0);
+        assert!(matches!(
+            store.get::<u32>("a"),
+            Err(crate::GetError::Deserialization(_))
+        ));
+
+        // nothing is shared between stores
+        assert!(PkvStore::in_memory().is_empty().unwrap());
+    }
+
+    #[test]
+    fn with_backend() {
+        setup();
+        let mut store = PkvStore::with_backend(Box::new(crate::MemoryStore::new()));
+        store.set_string("name", "alice").unwrap();
+        store.set("gold", &10u32).unwrap();
+        store.set_raw("icon", &[1, 2, 3]).unwrap();
+        assert_eq!(store.get::<String>("name").unwrap(), "alice");
+        assert_eq!(store.get_raw("icon").unwrap(), [1, 2, 3]);
+        assert_eq!(
+            store
+                .update::<u32>("gold", |gold| gold.map(|g| g + 5))
+                .unwrap(),
+            Some(15)
+        );
+        assert_eq!(store.scan_prefix::<String>("na").count(), 1);
+        assert_eq!(store.len().unwrap(), 3);
+        store
+            .namespace("profile")
+            .set_string("name", "bob")
+            .unwrap();
+        store.namespace("profile").clear().unwrap();
+        assert_eq!(
+            store.keys().collect::<Result<Vec<_>, _>>().unwrap(),
+            ["gold", "icon", "name"]
+        );
+        store.clear().unwrap();
+        assert!(store.is_empty().unwrap());
+    }
+
+    #[test]
+    fn error_kind() {
+        use crate::ErrorKind;
+
+        setup();
+        for_each_store(|mut store| {
+            store.set_string("name", "alice").unwrap();
+
+            let err = store.get::<String>("missing").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+            let err = store.get::<u32>("name").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Deserialization);
+            let err = store.remove_and_get::<u32>("name").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Deserialization);
+            assert_eq!(store.remove_and_get::<String>("missing").unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        use crate::CasError;
+
+        setup();
+        for_each_store(|mut store| {
+            store.compare_and_swap("gold", None, Some(&10)).unwrap();
+            assert_eq!(store.get::<u32>("gold").unwrap(), 10);
+
+            let err = store
+                .compare_and_swap("gold", Some(&5), Some(&20))
+                .unwrap_err();
+            assert!(matches!(err, CasError::Mismatch { current: Some(10) }));
+            assert_eq!(store.get::<u32>("gold").unwrap(), 10);
+
+            let err = store.compare_and_swap("gold", None, Some(&20)).unwrap_err();
+            assert!(matches!(err, CasError::Mismatch { current: Some(10) }));
+
+            store.compare_and_swap("gold", Some(&10), None).unwrap();
+            assert!(!store.contains_key("gold").unwrap());
+
+            // raw values never match, even with the same bytes
+            store
+                .set_raw("raw", &rmp_serde::to_vec(&5u32).unwrap())
+                .unwrap();
+            let err = store
+                .compare_and_swap("raw", Some(&5u32), Some(&6))
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                CasError::Get(crate::GetError::Deserialization(_))
+            ));
+            assert!(store.get_raw("raw").is_ok());
+        });
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        use crate::UpdateError;
+
+        setup();
+        for_each_store(|mut store| {
+            let volume = store.get_or_insert_with("volume", || 8u8).unwrap();
+            assert_eq!(volume, 8);
+            let volume = store
+                .get_or_insert_with("volume", || -> u8 { panic!("already inserted") })
+                .unwrap();
+            assert_eq!(volume, 8);
+
+            store.set_string("name", "alice").unwrap();
+            let err = store.get_or_insert_with("name", || 0u8).unwrap_err();
+            assert!(matches!(err, UpdateError::Get(_)));
+            assert_eq!(store.get::<String>("name").unwrap(), "alice");
+        });
+    }
+
+    #[test]
+    fn raw() {
+        use crate::GetError;
+
+        setup();
+        let mut store = PkvStore::in_memory();
+        let bytes = [0x89, b'P', b'N', b'G', 0, 0xff];
+        store.set_raw("thumbnail", &bytes).unwrap();
+        assert_eq!(store.get_raw("thumbnail").unwrap(), bytes);
+        assert!(store.get::<String>("thumbnail").is_err());
+
+        store.set_raw("empty", &[]).unwrap();
+        assert!(store.get_raw("empty").unwrap().is_empty());
+
+        store.set_string("name", "alice").unwrap();
+        let err = store.get_raw("name").unwrap_err();
+        assert!(matches!(err, GetError::NotRaw));
+    }
+
+    #[test]
+    fn typed_keys() {
+        use crate::PkvKey;
+
+        const USER: PkvKey<User> = PkvKey::new("user");
+
+        setup();
+        let mut store = PkvStore::in_memory();
+        let user = User {
+            name: "alice".to_string(),
+            age: 32,
+        };
+        store.set_typed(USER, &user).unwrap();
+        assert_eq!(store.get_typed(USER).unwrap(), user);
+
+        store.remove_typed(USER).unwrap();
+        assert!(store.get_typed(USER).is_err());
+    }
+
+    #[test]
+    fn namespace() {
+        setup();
+        for_each_store(|mut store| {
+            store.set_string("name", "root").unwrap();
+
+            let mut profile = store.namespace("profile1");
+            profile.set_string("name", "alice").unwrap();
+            profile.namespace("stats").set("level", &3).unwrap();
+            assert_eq!(profile.get::<String>("name").unwrap(), "alice");
+            let mut keys = profile.keys().collect::<Result<Vec<_>, _>>().unwrap();
+            keys.sort();
+            assert_eq!(keys, ["name", "stats/level"]);
+
+            store
+                .namespace("profile10")
+                .set_string("name", "bob")
+                .unwrap();
+            assert_eq!(store.get::<String>("profile1/name").unwrap(), "alice");
+            assert_eq!(store.get::<u32>("profile1/stats/level").unwrap(), 3);
+
+            store.namespace("profile1").clear().unwrap();
+            let mut keys = store.keys().collect::<Result<Vec<_>, _>>().unwrap();
+            keys.sort();
+            assert_eq!(keys, ["name", "profile10/name"]);
+        });
+    }
+
+    #[cfg(wasm)]
+    #[test]
+    fn local_storage_legacy_keys() {
+        use crate::{JsonCodec, LocalStorageStore};
+        setup();
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        // written by earlier versions, right after the names of the stores
+        storage.set_item("BevyPkv.legacyfoo", "\"app\"").unwrap();
+        storage.set_item("BevyPkv.legacy2foo", "\"app2\"").unwrap();
+
+        let app = LocalStorageStore::open("BevyPkv", "legacy").unwrap();
+        let app2 = LocalStorageStore::open("BevyPkv", "legacy2").unwrap();
+        let mut app = PkvStore::with_backend(Box::new(app)).with_codec(JsonCodec);
+        let mut app2 = PkvStore::with_backend(Box::new(app2)).with_codec(JsonCodec);
+        // left over from earlier runs
+        app.clear().unwrap();
+        app2.clear().unwrap();
+        // opening a store doesn't touch legacy keys
+        assert_eq!(app.keys().count(), 0);
+        assert!(storage.get_item("BevyPkv.legacy2foo").unwrap().is_some());
+
+        app2.set_string("bar", "app2").unwrap();
+        LocalStorageStore::open("BevyPkv", "legacy")
+            .unwrap()
+            .migrate_legacy_keys()
+            .unwrap();
+        assert_eq!(app.get::<String>("foo").unwrap(), "app");
+        // the stores still don't see each other's keys
+        assert!(!app.contains_key("2bar").unwrap());
+        assert!(!app2.contains_key("foo").unwrap());
+
+        LocalStorageStore::open("BevyPkv", "legacy2")
+            .unwrap()
+            .migrate_legacy_keys()
+            .unwrap();
+        assert_eq!(app2.get::<String>("foo").unwrap(), "app2");
+        assert_eq!(app2.get::<String>("bar").unwrap(), "app2");
+    }
 }
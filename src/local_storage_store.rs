@@ -1,44 +1,57 @@
-use crate::{Location, PlatformDefault, StoreImpl};
+use crate::envelope::Entry;
+use crate::error::impl_from_backend_errors;
+use crate::namespace::KeyPrefix;
+use crate::{
+    BackendEntries, CasError, ErrorKind, GetError, Location, OpenError, PkvBackend,
+    PlatformDefault, RemoveError, SetError,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::ops::{Bound, RangeBounds};
 
-#[derive(Debug, Default)]
+/// Prefix for base64 encoded bytes, which can never be the start of a json value
+const BINARY_PREFIX: &str = "bytes:";
+/// Prefix for a value with a base64 encoded header, followed by [`HEADER_END`] and the
+/// payload as text, so JSON values stay readable
+const HEADER_PREFIX: &str = "pkv:";
+const HEADER_END: char = ':';
+/// Put in front of the keys of all stores, followed by the name of the store and a
+/// `/`, so e.g. `Org.App` doesn't see the keys of `Org.App2`
+const KEY_PREFIX: &str = "bevy_pkv/";
+
+/// A [`PkvBackend`] storing the data in the browser's
+/// [`localStorage`](https://developer.mozilla.org/en-US/docs/Web/API/Window/localStorage)
+///
+/// Available on wasm only.
+#[derive(Debug)]
 pub struct LocalStorageStore {
-    prefix: String,
+    /// The name of the store, `Organization.Application`
+    name: String,
+    prefix: KeyPrefix,
 }
 
-pub use LocalStorageStore as InnerStore;
-
+/// A JavaScript exception from the `Storage` api
+///
+/// `JsValue` can't be sent between threads, so only its debug representation is kept.
 #[derive(thiserror::Error, Debug)]
-pub enum GetError {
-    #[error("No value found for the given key")]
-    NotFound,
-    #[error("error deserializing json")]
-    Json(#[from] serde_json::Error),
-    #[error("JavaScript error from getItem")]
-    GetItem(wasm_bindgen::JsValue),
+#[error("JavaScript error from {method}: {message}")]
+struct JsError {
+    method: &'static str,
+    message: String,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum SetError {
-    #[error("JavaScript error from setItem")]
-    SetItem(wasm_bindgen::JsValue),
-    #[error("Error serializing as json")]
-    Json(#[from] serde_json::Error),
-    #[error("JavaScript error from clear")]
-    Clear(wasm_bindgen::JsValue),
+fn js_error(method: &'static str) -> impl Fn(wasm_bindgen::JsValue) -> JsError {
+    move |value| JsError {
+        method,
+        message: format!("{value:?}"),
+    }
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum RemoveError {
-    #[error("No value found for the given key")]
-    NotFound,
-    #[error("error deserializing json")]
-    Json(#[from] serde_json::Error),
-    #[error("JavaScript error from getItem")]
-    GetItem(wasm_bindgen::JsValue),
-    #[error("JavaScript error from clear")]
-    Clear(wasm_bindgen::JsValue),
+fn classify(err: JsError) -> (ErrorKind, Box<dyn std::error::Error + Send + Sync>) {
+    (ErrorKind::Backend, err.into())
 }
 
+impl_from_backend_errors!(classify: JsError);
+
 impl LocalStorageStore {
     fn storage(&self) -> web_sys::Storage {
         web_sys::window()
@@ -48,84 +61,237 @@ impl LocalStorageStore {
             .expect("No local storage")
     }
 
-    pub(crate) fn new(constructor_bundle: Location) -> Self {
+    /// Opens the storage of the given organization and application, as used by
+    /// [`PkvStore::new`](crate::PkvStore::new)
+    pub fn open(organization: &str, application: &str) -> Result<Self, OpenError> {
+        let config = PlatformDefault {
+            qualifier: None,
+            organization: organization.to_string(),
+            application: application.to_string(),
+        };
+        Self::new(Location::PlatformDefault(&config))
+    }
+
+    pub(crate) fn new(constructor_bundle: Location) -> Result<Self, OpenError> {
+        let window = web_sys::window().ok_or_else(|| OpenError::Backend("No window".into()))?;
+        match window.local_storage() {
+            Ok(Some(_)) => {}
+            // e.g. disabled by the user, or denied in a sandboxed iframe
+            _ => return Err(OpenError::Backend("Local storage is not available".into())),
+        }
         let Location::PlatformDefault(config) = constructor_bundle;
         let PlatformDefault {
             qualifier,
             organization,
             application,
         } = config;
-        Self {
-            prefix: match qualifier.as_deref() {
-                Some(qualifier) => format!("{qualifier}.{organization}.{application}"),
-                None => format!("{organization}.{application}"),
-            },
+        let name = match qualifier.as_deref() {
+            Some(qualifier) => format!("{qualifier}.{organization}.{application}"),
+            None => format!("{organization}.{application}"),
+        };
+        Ok(Self {
+            prefix: KeyPrefix::new(&format!("{KEY_PREFIX}{name}")),
+            name,
+        })
+    }
+
+    /// Local storage is never created, so this is the same as `new`.
+    /// Writes are rejected by `PkvStore`.
+    pub(crate) fn new_read_only(location: Location) -> Result<Self, OpenError> {
+        Self::new(location)
+    }
+
+    /// Copies the values written by earlier versions of bevy_pkv, which put the key
+    /// right after the name of the store, to the current key format
+    ///
+    /// Keys that already have a value in the current format are skipped. The legacy
+    /// keys are left in place, since they can't be told apart from the legacy keys of
+    /// a store with a name that starts with the name of this one, e.g. `Org.App2` for
+    /// `Org.App`, or from keys on the same origin that have nothing to do with
+    /// bevy_pkv. Those are copied as well, e.g. `Org.App2` key `foo` as `2foo`.
+    ///
+    /// ```rust no_run
+    /// # use bevy_pkv::{JsonCodec, LocalStorageStore, PkvStore};
+    /// let backend = LocalStorageStore::open("FooCompany", "BarGame")?;
+    /// backend.migrate_legacy_keys()?;
+    /// let store = PkvStore::with_backend(Box::new(backend)).with_codec(JsonCodec);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn migrate_legacy_keys(&self) -> Result<(), SetError> {
+        let storage = self.storage();
+        let length = storage.length().map_err(js_error("length"))?;
+        let mut legacy_keys = Vec::new();
+        for index in 0..length {
+            if let Some(key) = storage.key(index).map_err(js_error("key"))? {
+                if key.starts_with(&self.name) && !key.starts_with(KEY_PREFIX) {
+                    legacy_keys.push(key);
+                }
+            }
         }
+        for legacy_key in legacy_keys {
+            let key = self.prefix.format_key(&legacy_key[self.name.len()..]);
+            let value = storage.get_item(&legacy_key).map_err(js_error("getItem"))?;
+            let current = storage.get_item(&key).map_err(js_error("getItem"))?;
+            if let (Some(value), None) = (value, current) {
+                storage
+                    .set_item(&key, &value)
+                    .map_err(js_error("setItem"))?;
+            }
+        }
+        Ok(())
     }
 
-    fn format_key(&self, key: &str) -> String {
-        format!("{}{}", self.prefix, key)
+    /// Collects the keys owned by this store up front, since local storage indices
+    /// shift when items are added or removed
+    fn collect_keys(&self) -> Result<Vec<String>, JsError> {
+        let storage = self.storage();
+        let length = storage.length().map_err(js_error("length"))?;
+        let mut keys = Vec::new();
+        for index in 0..length {
+            if let Some(key) = storage.key(index).map_err(js_error("key"))? {
+                if let Some(key) = key.strip_prefix(self.prefix.as_str()) {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+        Ok(keys)
     }
-}
 
-impl StoreImpl for LocalStorageStore {
-    type GetError = GetError;
-    type SetError = SetError;
-    type RemoveError = RemoveError;
+    fn get_item(&self, key: &str) -> Result<Option<Vec<u8>>, JsError> {
+        let entry = self.storage().get_item(&self.prefix.format_key(key));
+        Ok(entry.map_err(js_error("getItem"))?.map(decode))
+    }
 
-    fn set_string(&mut self, key: &str, value: &str) -> Result<(), SetError> {
-        let json = serde_json::to_string(value)?;
+    fn set_item(&self, key: &str, value: &[u8]) -> Result<(), JsError> {
+        let entry = encode(value);
         let storage = self.storage();
-        let key = self.format_key(key);
-        storage.set_item(&key, &json).map_err(SetError::SetItem)?;
-        Ok(())
+        storage
+            .set_item(&self.prefix.format_key(key), &entry)
+            .map_err(js_error("setItem"))
     }
 
-    fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> Result<T, GetError> {
+    fn remove_item(&self, key: &str) -> Result<(), JsError> {
         let storage = self.storage();
-        let key = self.format_key(key);
-        let entry = storage.get_item(&key).map_err(GetError::GetItem)?;
-        let json = entry.as_ref().ok_or(GetError::NotFound)?;
-        let value: T = serde_json::from_str(json)?;
+        storage
+            .remove_item(&self.prefix.format_key(key))
+            .map_err(js_error("removeItem"))
+    }
+}
+
+/// Local storage can only hold strings, so bytes that aren't valid UTF-8, or that
+/// could be mistaken for encoded bytes, are stored base64 encoded after a prefix
+///
+/// The header `PkvStore` puts in front of values isn't valid UTF-8, so only the header
+/// is base64 encoded if the payload is text, which keeps JSON values small and readable.
+fn encode(bytes: &[u8]) -> String {
+    let is_encoded =
+        |text: &str| text.starts_with(BINARY_PREFIX) || text.starts_with(HEADER_PREFIX);
+    if let Some(header_len) = Entry::header_len(bytes) {
+        let (header, payload) = bytes.split_at(header_len);
+        if let Ok(payload) = std::str::from_utf8(payload) {
+            let header = BASE64.encode(header);
+            return format!("{HEADER_PREFIX}{header}{HEADER_END}{payload}");
+        }
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !is_encoded(text) => text.to_string(),
+        _ => format!("{BINARY_PREFIX}{}", BASE64.encode(bytes)),
+    }
+}
+
+fn decode(entry: String) -> Vec<u8> {
+    if let Some(base64) = entry.strip_prefix(BINARY_PREFIX) {
+        return BASE64.decode(base64).unwrap_or_else(|_| entry.into_bytes());
+    }
+    let header = entry
+        .strip_prefix(HEADER_PREFIX)
+        .and_then(|rest| rest.split_once(HEADER_END))
+        .and_then(|(header, payload)| Some((BASE64.decode(header).ok()?, payload)));
+    match header {
+        Some((mut bytes, payload)) => {
+            bytes.extend(payload.as_bytes());
+            bytes
+        }
+        None => entry.into_bytes(),
+    }
+}
+
+impl PkvBackend for LocalStorageStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        Ok(self.get_item(key)?)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError> {
+        Ok(self.set_item(key, value)?)
+    }
+
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        let value = self.get_item(key)?;
+        if value.is_some() {
+            self.remove_item(key)?;
+        }
         Ok(value)
     }
 
-    fn set<T: serde::Serialize>(&mut self, key: &str, value: &T) -> Result<(), SetError> {
-        let json = serde_json::to_string(value)?;
-        let storage = self.storage();
-        let key = self.format_key(key);
-        storage.set_item(&key, &json).map_err(SetError::SetItem)?;
+    /// JavaScript is single threaded, so nothing else in this page can write between
+    /// the read and the write. Other tabs of the same origin still can.
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        let current = self.get_item(key).map_err(GetError::from)?;
+        if current.as_deref() != expected {
+            return Err(CasError::Mismatch { current });
+        }
+        match new {
+            Some(bytes) => self.set_item(key, bytes),
+            None => self.remove_item(key),
+        }
+        .map_err(SetError::from)?;
         Ok(())
     }
 
     /// Because the data is cleared by looping through it, it may take time or run slowly
-    fn clear(&mut self) -> Result<(), SetError> {
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
         let storage = self.storage();
-        let length = storage.length().map_err(SetError::Clear)?;
-        let prefix = &self.prefix;
+        let length = storage.length().map_err(js_error("length"))?;
+        let prefix = &self.prefix.format_key(prefix);
         for index in (0..length).rev() {
-            if let Some(key) = storage.key(index).map_err(SetError::Clear)? {
+            if let Some(key) = storage.key(index).map_err(js_error("key"))? {
                 if key.starts_with(prefix) {
-                    storage.remove_item(&key).map_err(SetError::Clear)?;
+                    storage.remove_item(&key).map_err(js_error("removeItem"))?;
                 }
             }
         }
         Ok(())
     }
 
-    fn remove(&mut self, key: &str) -> Result<(), Self::RemoveError> {
-        let storage = self.storage();
-        let key = self.format_key(key);
-        storage.remove_item(&key).map_err(RemoveError::Clear)?;
-        Ok(())
+    fn keys(&self) -> Box<dyn Iterator<Item = Result<String, GetError>> + '_> {
+        match self.collect_keys() {
+            Ok(keys) => Box::new(keys.into_iter().map(Ok)),
+            Err(err) => Box::new(std::iter::once(Err(err.into()))),
+        }
     }
 
-    fn remove_and_get<T: serde::de::DeserializeOwned>(
-        &mut self,
-        key: &str,
-    ) -> Result<Option<T>, Self::RemoveError> {
-        let previous_value = self.get(key).map_err(|_| RemoveError::NotFound)?;
-        self.remove(key)?;
-        Ok(Some(previous_value))
+    /// Local storage is unordered, so this collects and sorts the matching keys
+    /// before reading their values
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        let mut keys = match self.collect_keys() {
+            Ok(keys) => keys,
+            Err(err) => return Box::new(std::iter::once(Err(err.into()))),
+        };
+        keys.retain(|key| range.contains(&key.as_str()));
+        keys.sort();
+        Box::new(
+            keys.into_iter()
+                .filter_map(|key| match self.get_item(&key) {
+                    Ok(Some(bytes)) => Some(Ok((key, bytes))),
+                    // removed since we listed the keys
+                    Ok(None) => None,
+                    Err(err) => Some(Err(err.into())),
+                }),
+        )
     }
 }
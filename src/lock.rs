@@ -0,0 +1,69 @@
+//! Handling stores that are locked by another process
+
+use std::time::{Duration, Instant};
+
+use crate::{read_only::ReadOnlyBackend, DefaultBackend, Location, OpenError, PkvStore};
+
+/// How long to wait between attempts to open a locked store
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What to do when the store is locked by another process, e.g. when the game is
+/// launched twice, or an editor has the store open
+///
+/// Used with [`PkvStore::try_new_with_lock_policy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Return [`OpenError::Locked`] right away
+    #[default]
+    Fail,
+    /// Keep retrying until the lock is released, and return [`OpenError::Locked`]
+    /// if it's still held after `timeout`
+    Retry {
+        /// How long to keep retrying
+        timeout: Duration,
+    },
+    /// Keep retrying like [`LockPolicy::Retry`], then open the store read-only, as
+    /// with [`PkvStore::open_read_only`]
+    ///
+    /// Whether reading is possible while another process has the store open depends
+    /// on the backend: RocksDB allows it, redb only if the other process opened the
+    /// store read-only too, and sled never does.
+    ReadOnly {
+        /// How long to keep retrying before falling back to read-only
+        timeout: Duration,
+    },
+}
+
+impl PkvStore {
+    pub(crate) fn open_with_lock_policy(
+        location: Location,
+        policy: LockPolicy,
+    ) -> Result<Self, OpenError> {
+        let timeout = match policy {
+            LockPolicy::Fail => Duration::ZERO,
+            LockPolicy::Retry { timeout } | LockPolicy::ReadOnly { timeout } => timeout,
+        };
+        // only read the clock once the store turned out to be locked, it's not
+        // available on wasm, where stores are never locked
+        let mut deadline = None;
+        loop {
+            let err = match DefaultBackend::new(location) {
+                Ok(backend) => return Ok(Self::from_default_backend(backend)),
+                Err(err @ OpenError::Locked { .. }) => err,
+                Err(err) => return Err(err),
+            };
+            let deadline = *deadline.get_or_insert_with(|| Instant::now() + timeout);
+            if Instant::now() < deadline {
+                std::thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+            return match policy {
+                LockPolicy::ReadOnly { .. } => {
+                    let backend = DefaultBackend::new_read_only(location)?;
+                    Ok(Self::from_default_backend(ReadOnlyBackend(backend)))
+                }
+                _ => Err(err),
+            };
+        }
+    }
+}
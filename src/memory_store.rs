@@ -0,0 +1,118 @@
+use crate::{BackendEntries, BatchOp, CasError, GetError, PkvBackend, RemoveError, SetError};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A [`PkvBackend`] keeping the data in memory, for tests and headless servers
+///
+/// Nothing is persisted, the data is gone when the store is dropped.
+/// Available on all platforms, see [`PkvStore::in_memory`](crate::PkvStore::in_memory).
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    map: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn map(&self) -> MutexGuard<'_, BTreeMap<String, Vec<u8>>> {
+        // the map is never left in an inconsistent state, so a panic while holding
+        // the lock doesn't matter
+        self.map.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PkvBackend for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        Ok(self.map().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError> {
+        self.map().insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        Ok(self.map().remove(key))
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        Ok(self.map().contains_key(key))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        let mut map = self.map();
+        let current = map.get(key);
+        if current.map(Vec::as_slice) != expected {
+            let current = current.cloned();
+            return Err(CasError::Mismatch { current });
+        }
+        match new {
+            Some(bytes) => map.insert(key.to_string(), bytes.to_vec()),
+            None => map.remove(key),
+        };
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), SetError> {
+        self.map().clear();
+        Ok(())
+    }
+
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
+        self.map().retain(|key, _| !key.starts_with(prefix));
+        Ok(())
+    }
+
+    /// Applies all operations while holding the lock, so the batch is atomic
+    fn write_batch(&self, ops: Vec<BatchOp>) -> Result<(), SetError> {
+        let mut map = self.map();
+        for op in ops {
+            match op {
+                BatchOp::Set(key, bytes) => {
+                    map.insert(key, bytes);
+                }
+                BatchOp::Remove(key) => {
+                    map.remove(&key);
+                }
+                BatchOp::Clear => map.clear(),
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, GetError> {
+        Ok(self.map().len())
+    }
+
+    /// Copies the matching entries up front, so the lock isn't held while iterating
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        // `BTreeMap::range` panics on these, while the other backends yield nothing
+        let inverted = match range {
+            (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start > end,
+            _ => false,
+        };
+        if inverted {
+            return Box::new(std::iter::empty());
+        }
+        let range = (range.0.map(str::to_string), range.1.map(str::to_string));
+        let entries: Vec<_> = self
+            .map()
+            .range(range)
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+}
@@ -0,0 +1,238 @@
+//! Views of a store where all keys share a prefix
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    ChangeKind, EntryMetadata, GetError, PkvKey, PkvStore, RemoveError, SetError, UpdateError,
+};
+
+/// A view of a [`PkvStore`] where all keys are transparently prefixed with a namespace
+///
+/// Useful for isolating subsystems, like settings, achievements or per-profile saves,
+/// that share a single store. A key `key` in namespace `name` is stored as `name/key`.
+/// Namespaces can be nested with [`Namespace::namespace`].
+///
+/// Created with [`PkvStore::namespace`].
+///
+/// ```rust no_run
+/// # use bevy_pkv::PkvStore;
+/// let mut store = PkvStore::new("FooCompany", "BarGame");
+/// let mut profile = store.namespace("profile1");
+/// profile.set_string("name", "alice")?;
+/// // only removes the keys in the namespace
+/// profile.clear()?;
+/// # Ok::<(), bevy_pkv::SetError>(())
+/// ```
+#[derive(Debug)]
+pub struct Namespace<'a> {
+    store: &'a mut PkvStore,
+    prefix: KeyPrefix,
+}
+
+/// The prefix put in front of keys to keep them apart from the other keys in the
+/// same storage, `name/` for the name of a namespace
+///
+/// Also used by `LocalStorageStore` to keep the keys of each store apart.
+#[derive(Debug, Clone)]
+pub(crate) struct KeyPrefix(String);
+
+impl KeyPrefix {
+    pub(crate) fn new(name: &str) -> Self {
+        Self(format!("{name}/"))
+    }
+
+    /// The prefix for `name` within this prefix
+    pub(crate) fn nested(&self, name: &str) -> Self {
+        Self(format!("{}{name}/", self.0))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn format_key(&self, key: &str) -> String {
+        format!("{}{}", self.0, key)
+    }
+}
+
+impl<'a> Namespace<'a> {
+    pub(crate) fn new(store: &'a mut PkvStore, name: &str) -> Self {
+        Self {
+            store,
+            prefix: KeyPrefix::new(name),
+        }
+    }
+
+    /// The prefix added to all keys in this namespace
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_str()
+    }
+
+    /// Get a nested namespace within this namespace
+    pub fn namespace(&mut self, name: &str) -> Namespace<'_> {
+        Namespace {
+            store: &mut *self.store,
+            prefix: self.prefix.nested(name),
+        }
+    }
+
+    /// Serialize and store the value
+    pub fn set<T: Serialize>(&mut self, key: impl AsRef<str>, value: &T) -> Result<(), SetError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.set(key, value)
+    }
+
+    /// More or less the same as `set::<String>`, but can take a `&str`
+    pub fn set_string(&mut self, key: impl AsRef<str>, value: &str) -> Result<(), SetError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.set_string(key, value)
+    }
+
+    /// Serialize and store the value, which expires after `ttl`
+    ///
+    /// See [`PkvStore::set_with_ttl`].
+    pub fn set_with_ttl<T: Serialize>(
+        &mut self,
+        key: impl AsRef<str>,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<(), SetError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.set_with_ttl(key, value, ttl)
+    }
+
+    /// Serialize and store the value, which expires at `expires_at`
+    ///
+    /// See [`PkvStore::set_expiring_at`].
+    pub fn set_expiring_at<T: Serialize>(
+        &mut self,
+        key: impl AsRef<str>,
+        value: &T,
+        expires_at: SystemTime,
+    ) -> Result<(), SetError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.set_expiring_at(key, value, expires_at)
+    }
+
+    /// Store the bytes as they are, without serializing them
+    ///
+    /// See [`PkvStore::set_raw`].
+    pub fn set_raw(&mut self, key: impl AsRef<str>, value: &[u8]) -> Result<(), SetError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.set_raw(key, value)
+    }
+
+    /// Serialize and store the value for the given typed key
+    pub fn set_typed<T: Serialize>(&mut self, key: PkvKey<T>, value: &T) -> Result<(), SetError> {
+        self.set(key.as_str(), value)
+    }
+
+    /// Get the value for the given key
+    /// returns Err(GetError::NotFound) if the key does not exist in the namespace.
+    pub fn get<T: DeserializeOwned>(&self, key: impl AsRef<str>) -> Result<T, GetError> {
+        self.store.get(self.prefix.format_key(key.as_ref()))
+    }
+
+    /// Get the bytes stored with [`Namespace::set_raw`] for the given key
+    pub fn get_raw(&self, key: impl AsRef<str>) -> Result<Vec<u8>, GetError> {
+        self.store.get_raw(self.prefix.format_key(key.as_ref()))
+    }
+
+    /// Get the value for the given typed key
+    pub fn get_typed<T: DeserializeOwned>(&self, key: PkvKey<T>) -> Result<T, GetError> {
+        self.get(key.as_str())
+    }
+
+    /// Returns `true` if the namespace contains a value for the given key
+    pub fn contains_key(&self, key: impl AsRef<str>) -> Result<bool, GetError> {
+        self.store
+            .contains_key(self.prefix.format_key(key.as_ref()))
+    }
+
+    /// Get the metadata of the value for the given key
+    ///
+    /// See [`PkvStore::metadata`].
+    pub fn metadata(&self, key: impl AsRef<str>) -> Result<EntryMetadata, GetError> {
+        self.store.metadata(self.prefix.format_key(key.as_ref()))
+    }
+
+    /// Atomically read, modify and write the value for the given key
+    ///
+    /// See [`PkvStore::update`].
+    pub fn update<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: impl AsRef<str>,
+        f: impl FnMut(Option<T>) -> Option<T>,
+    ) -> Result<Option<T>, UpdateError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.update(key, f)
+    }
+
+    /// Get the value for the given key, or insert the value returned by `f` if there is none
+    ///
+    /// See [`PkvStore::get_or_insert_with`].
+    pub fn get_or_insert_with<T: Serialize + DeserializeOwned>(
+        &mut self,
+        key: impl AsRef<str>,
+        f: impl FnOnce() -> T,
+    ) -> Result<T, UpdateError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.get_or_insert_with(key, f)
+    }
+
+    /// Remove the value from the namespace for the given key
+    /// returns the removed value if one existed
+    pub fn remove_and_get<T: DeserializeOwned>(
+        &mut self,
+        key: impl AsRef<str>,
+    ) -> Result<Option<T>, RemoveError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.remove_and_get(key)
+    }
+
+    /// Remove the value from the namespace for the given key
+    pub fn remove(&mut self, key: impl AsRef<str>) -> Result<(), RemoveError> {
+        let key = self.prefix.format_key(key.as_ref());
+        self.store.remove(key)
+    }
+
+    /// Remove the value from the namespace for the given typed key
+    pub fn remove_typed<T>(&mut self, key: PkvKey<T>) -> Result<(), RemoveError> {
+        self.remove(key.as_str())
+    }
+
+    /// Iterate over all keys in the namespace, with the namespace prefix stripped
+    pub fn keys(&self) -> impl Iterator<Item = Result<String, GetError>> + '_ {
+        let namespace_len = self.prefix.as_str().len();
+        self.store
+            .backend
+            .scan_prefix(self.prefix.as_str())
+            .map(move |entry| entry.map(|(key, _)| key[namespace_len..].to_string()))
+    }
+
+    /// Iterate over all key value pairs in the namespace where the key starts with `prefix`
+    ///
+    /// See [`PkvStore::scan_prefix`]. Keys are yielded with the namespace prefix stripped.
+    pub fn scan_prefix<'b, T: DeserializeOwned + 'b>(
+        &'b self,
+        prefix: impl AsRef<str>,
+    ) -> impl Iterator<Item = Result<(String, Result<T, GetError>), GetError>> + 'b {
+        let namespace_len = self.prefix.as_str().len();
+        self.store
+            .scan_prefix(self.prefix.format_key(prefix.as_ref()))
+            .map(move |entry| {
+                let (key, value) = entry?;
+                Ok((key[namespace_len..].to_string(), value))
+            })
+    }
+
+    /// Clear all key values in the namespace, leaving the rest of the store untouched
+    pub fn clear(&mut self) -> Result<(), SetError> {
+        self.store.backend.clear_prefix(self.prefix.as_str())?;
+        self.store
+            .watchers
+            .notify(self.prefix.as_str(), ChangeKind::Cleared);
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 //! Convenient re-exports for common bevy_pkv functionality
 
-pub use crate::PkvStore;
+pub use crate::{PkvKey, PkvStore};
 
 #[cfg(feature = "bevy")]
 pub use crate::{PersistentResourceAppExtensions, PersistentResourcePlugin};
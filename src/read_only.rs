@@ -0,0 +1,75 @@
+//! Rejecting writes to stores opened with `PkvStore::open_read_only`
+
+use std::ops::Bound;
+use std::sync::mpsc::Sender;
+
+use crate::{
+    BackendEntries, BatchOp, CasError, Changed, GetError, PkvBackend, RemoveError, SetError,
+};
+
+/// Forwards reads to the wrapped backend, and fails all writes with a `ReadOnly` error
+#[derive(Debug)]
+pub(crate) struct ReadOnlyBackend<B>(pub(crate) B);
+
+impl<B: PkvBackend> PkvBackend for ReadOnlyBackend<B> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        self.0.get(key)
+    }
+
+    fn set(&self, _key: &str, _value: &[u8]) -> Result<(), SetError> {
+        Err(SetError::ReadOnly)
+    }
+
+    fn remove(&self, _key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        Err(RemoveError::ReadOnly)
+    }
+
+    fn compare_and_swap(
+        &self,
+        _key: &str,
+        _expected: Option<&[u8]>,
+        _new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        Err(SetError::ReadOnly.into())
+    }
+
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        self.0.range(range)
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        self.0.contains_key(key)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> BackendEntries<'_> {
+        self.0.scan_prefix(prefix)
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = Result<String, GetError>> + '_> {
+        self.0.keys()
+    }
+
+    fn len(&self) -> Result<usize, GetError> {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, GetError> {
+        self.0.is_empty()
+    }
+
+    fn watch_prefix(&self, prefix: &str, sender: Sender<Changed>) -> bool {
+        self.0.watch_prefix(prefix, sender)
+    }
+
+    fn clear_prefix(&self, _prefix: &str) -> Result<(), SetError> {
+        Err(SetError::ReadOnly)
+    }
+
+    fn clear(&self) -> Result<(), SetError> {
+        Err(SetError::ReadOnly)
+    }
+
+    fn write_batch(&self, _ops: Vec<BatchOp>) -> Result<(), SetError> {
+        Err(SetError::ReadOnly)
+    }
+}
@@ -1,9 +1,23 @@
-use crate::{Location, StoreImpl};
-use redb::{Database, ReadableDatabase, TableDefinition};
-use serde::{de::DeserializeOwned, Serialize};
+use crate::error::impl_from_backend_errors;
+use crate::{
+    BackendEntries, BatchOp, CasError, ErrorKind, GetError, Location, OpenError, PkvBackend,
+    RemoveError, SetError,
+};
+use redb::{
+    Database, ReadOnlyDatabase, ReadableDatabase, ReadableTable, ReadableTableMetadata,
+    TableDefinition,
+};
 use std::fmt::{Debug, Formatter};
+use std::ops::Bound;
+use std::path::Path;
+/// A [`PkvBackend`] storing the data in a [redb](https://docs.rs/redb) database
 pub struct ReDbStore {
-    db: Database,
+    db: Db,
+}
+
+enum Db {
+    ReadWrite(Database),
+    ReadOnly(ReadOnlyDatabase),
 }
 impl Debug for ReDbStore {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -11,177 +25,237 @@ impl Debug for ReDbStore {
         Ok(())
     }
 }
-pub use ReDbStore as InnerStore;
-
-/// Errors that can occur during `PkvStore::get`
-#[derive(thiserror::Error, Debug)]
-pub enum GetError {
-    /// An internal storage error from the `redb` crate
-    #[error("ReDbStorageError error")]
-    ReDbStorageError(#[from] redb::StorageError),
-    /// An internal transaction error from the `redb` crate
-    #[error("ReDbTransactionError error")]
-    ReDbTransactionError(#[from] redb::TransactionError),
-    /// An internal table error from the `redb` crate
-    #[error("ReDbTableError error")]
-    ReDbTableError(#[from] redb::TableError),
-    /// The value for the given key was not found
-    #[error("No value found for the given key")]
-    NotFound,
-    /// Error when deserializing the value
-    #[error("MessagePack deserialization error")]
-    MessagePack(#[from] rmp_serde::decode::Error),
-}
-
-/// Errors that can occur during `PkvStore::`
-#[derive(thiserror::Error, Debug)]
-pub enum RemoveError {
-    /// An internal commit error from the `redb` crate
-    #[error("ReDbCommitError error")]
-    ReDbCommitError(#[from] redb::CommitError),
-    /// An internal storage error from the `redb` crate
-    #[error("ReDbStorageError error")]
-    ReDbStorageError(#[from] redb::StorageError),
-    /// An internal transaction error from the `redb` crate
-    #[error("ReDbTransactionError error")]
-    ReDbTransactionError(#[from] redb::TransactionError),
-    /// An internal table error from the `redb` crate
-    #[error("ReDbTableError error")]
-    ReDbTableError(#[from] redb::TableError),
-    /// Error when serializing the value
-    #[error("MessagePack serialization error")]
-    MessagePack(#[from] rmp_serde::encode::Error),
+fn classify(err: impl Into<redb::Error>) -> (ErrorKind, Box<dyn std::error::Error + Send + Sync>) {
+    let err = err.into();
+    let kind = match err {
+        redb::Error::Io(_) => ErrorKind::Io,
+        redb::Error::Corrupted(_) => ErrorKind::Corruption,
+        _ => ErrorKind::Backend,
+    };
+    (kind, err.into())
 }
 
-/// Errors that can occur during `PkvStore::set`
-#[derive(thiserror::Error, Debug)]
-pub enum SetError {
-    /// An internal commit error from the `redb` crate
-    #[error("ReDbCommitError error")]
-    ReDbCommitError(#[from] redb::CommitError),
-    /// An internal storage error from the `redb` crate
-    #[error("ReDbStorageError error")]
-    ReDbStorageError(#[from] redb::StorageError),
-    /// An internal transaction error from the `redb` crate
-    #[error("ReDbTransactionError error")]
-    ReDbTransactionError(#[from] redb::TransactionError),
-    /// An internal table error from the `redb` crate
-    #[error("ReDbTableError error")]
-    ReDbTableError(#[from] redb::TableError),
-    /// Error when serializing the value
-    #[error("MessagePack serialization error")]
-    MessagePack(#[from] rmp_serde::encode::Error),
-}
+impl_from_backend_errors!(
+    classify: redb::StorageError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::CommitError,
+);
 
 impl ReDbStore {
-    pub(crate) fn new(location: Location) -> Self {
-        let dir_path = location.get_path();
-        std::fs::create_dir_all(&dir_path)
-            .expect("Failed to create directory to init key value store");
-        let db_path = dir_path.join("bevy_pkv.redb");
-        let db = Database::create(db_path).expect("Failed to init key value store");
-
-        let write_txn = db.begin_write().unwrap();
-        write_txn.open_table(TABLE).unwrap();
-        write_txn.commit().unwrap();
+    /// Opens or creates a database named `bevy_pkv.redb` in the given directory
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        Self::new(Location::CustomPath(path.as_ref()))
+    }
 
-        Self { db }
+    pub(crate) fn new(location: Location) -> Result<Self, OpenError> {
+        Self::new_with_filename(location, "bevy_pkv.redb")
     }
 
-    pub(crate) fn new_with_filename(location: Location, filename: &str) -> Self {
+    pub(crate) fn new_with_filename(location: Location, filename: &str) -> Result<Self, OpenError> {
         let dir_path = location.get_path();
-        std::fs::create_dir_all(&dir_path)
-            .expect("Failed to create directory to init key value store");
+        std::fs::create_dir_all(&dir_path)?;
         let db_path = dir_path.join(filename);
-        let db = Database::create(db_path).expect("Failed to init key value store");
+        let db = Database::create(&db_path).map_err(|err| open_error(err, &db_path))?;
 
-        let write_txn = db.begin_write().unwrap();
-        write_txn.open_table(TABLE).unwrap();
-        write_txn.commit().unwrap();
+        let write_txn = db
+            .begin_write()
+            .map_err(|err| OpenError::Backend(err.into()))?;
+        write_txn
+            .open_table(TABLE)
+            .map_err(|err| OpenError::Backend(err.into()))?;
+        write_txn
+            .commit()
+            .map_err(|err| OpenError::Backend(err.into()))?;
 
-        Self { db }
+        Ok(Self {
+            db: Db::ReadWrite(db),
+        })
     }
-}
 
-const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("redb");
+    /// Opens an existing database without creating or writing anything
+    pub(crate) fn new_read_only(location: Location) -> Result<Self, OpenError> {
+        let db_path = location.get_path().join("bevy_pkv.redb");
+        let db = ReadOnlyDatabase::open(&db_path).map_err(|err| open_error(err, &db_path))?;
+        Ok(Self {
+            db: Db::ReadOnly(db),
+        })
+    }
 
-impl StoreImpl for ReDbStore {
-    type GetError = GetError;
-    type SetError = SetError;
-    type RemoveError = RemoveError;
+    fn begin_read(&self) -> Result<redb::ReadTransaction, redb::TransactionError> {
+        match &self.db {
+            Db::ReadWrite(db) => db.begin_read(),
+            Db::ReadOnly(db) => db.begin_read(),
+        }
+    }
 
-    /// More or less the same as set::<String>, but can take a &str
-    fn set_string(&mut self, key: &str, value: &str) -> Result<(), Self::SetError> {
-        let bytes = rmp_serde::to_vec(value)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE).unwrap();
-            table.insert(key, bytes.as_slice())?;
+    /// Returns `None` if the database was opened read-only
+    fn writable(&self) -> Option<&Database> {
+        match &self.db {
+            Db::ReadWrite(db) => Some(db),
+            Db::ReadOnly(_) => None,
         }
-        write_txn.commit()?;
+    }
+}
 
-        Ok(())
+/// redb locks the database file itself
+fn open_error(err: redb::DatabaseError, db_path: &Path) -> OpenError {
+    match err {
+        redb::DatabaseError::DatabaseAlreadyOpen => OpenError::Locked {
+            path: db_path.to_path_buf(),
+            source: err.into(),
+        },
+        redb::DatabaseError::Storage(redb::StorageError::Io(err)) => OpenError::Io(err),
+        redb::DatabaseError::Storage(err @ redb::StorageError::Corrupted(_)) => {
+            OpenError::Corruption(err.into())
+        }
+        err => OpenError::Backend(err.into()),
     }
+}
 
-    /// Get the value for the given key
-    /// returns Err(GetError::NotFound) if the key does not exist in the key value store.
-    fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, Self::GetError> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE)?;
-        let key = table.get(key)?.ok_or(Self::GetError::NotFound)?;
-        let bytes = key.value();
-        let value = rmp_serde::from_slice(bytes)?;
-        Ok(value)
+const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("redb");
+
+impl PkvBackend for ReDbStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        let read_txn = self.begin_read()?;
+        let table = match read_txn.open_table(TABLE) {
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            table => table?,
+        };
+        Ok(table.get(key)?.map(|bytes| bytes.value().to_vec()))
     }
 
-    /// Serialize and store the value
-    fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), Self::SetError> {
-        let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
-        value.serialize(&mut serializer)?;
-        let write_txn = self.db.begin_write()?;
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError> {
+        let db = self.writable().ok_or(SetError::ReadOnly)?;
+        let write_txn = db.begin_write()?;
         {
-            let mut table = write_txn.open_table(TABLE).unwrap();
-            table.insert(key, serializer.into_inner().as_slice())?;
+            let mut table = write_txn.open_table(TABLE)?;
+            table.insert(key, value)?;
         }
         write_txn.commit()?;
 
         Ok(())
     }
 
-    fn remove_and_get<T: DeserializeOwned>(
-        &mut self,
-        key: &str,
-    ) -> Result<Option<T>, Self::RemoveError> {
-        let value: Option<T>;
-        let write_txn = self.db.begin_write()?;
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        let value;
+        let db = self.writable().ok_or(RemoveError::ReadOnly)?;
+        let write_txn = db.begin_write()?;
         {
-            let mut table = write_txn.open_table(TABLE).unwrap();
-            value = match table.remove(key)? {
-                Some(kv) => rmp_serde::from_slice(kv.value()).ok(),
-                None => None,
-            };
+            let mut table = write_txn.open_table(TABLE)?;
+            value = table.remove(key)?.map(|bytes| bytes.value().to_vec());
         }
         write_txn.commit()?;
 
         Ok(value)
     }
 
-    fn remove(&mut self, key: &str) -> Result<(), Self::RemoveError> {
-        let write_txn = self.db.begin_write()?;
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        let read_txn = self.begin_read()?;
+        let table = match read_txn.open_table(TABLE) {
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(false),
+            table => table?,
+        };
+        Ok(table.get(key)?.is_some())
+    }
+
+    /// Compares and writes in a single write transaction
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        let db = self.writable().ok_or(SetError::ReadOnly)?;
+        let write_txn = db.begin_write().map_err(SetError::from)?;
         {
-            let mut table = write_txn.open_table(TABLE).unwrap();
-            table.remove(key)?;
+            let mut table = write_txn.open_table(TABLE).map_err(SetError::from)?;
+            let current = table
+                .get(key)
+                .map_err(SetError::from)?
+                .map(|bytes| bytes.value().to_vec());
+            if current.as_deref() != expected {
+                // dropping the transaction aborts it
+                return Err(CasError::Mismatch { current });
+            }
+            match new {
+                Some(bytes) => table.insert(key, bytes).map(drop),
+                None => table.remove(key).map(drop),
+            }
+            .map_err(SetError::from)?;
         }
-        write_txn.commit()?;
-
+        write_txn.commit().map_err(SetError::from)?;
         Ok(())
     }
 
     /// Clear all keys and their values
-    fn clear(&mut self) -> Result<(), Self::SetError> {
-        let write_txn = self.db.begin_write()?;
+    fn clear(&self) -> Result<(), SetError> {
+        let db = self.writable().ok_or(SetError::ReadOnly)?;
+        let write_txn = db.begin_write()?;
         write_txn.delete_table(TABLE)?;
         write_txn.commit()?;
         Ok(())
     }
+
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
+        let db = self.writable().ok_or(SetError::ReadOnly)?;
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.retain_in::<&str, _>(prefix.., |key, _| !key.starts_with(prefix))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Applies all operations in a single write transaction
+    fn write_batch(&self, ops: Vec<BatchOp>) -> Result<(), SetError> {
+        let db = self.writable().ok_or(SetError::ReadOnly)?;
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            for op in &ops {
+                match op {
+                    BatchOp::Set(key, bytes) => {
+                        table.insert(key.as_str(), bytes.as_slice())?;
+                    }
+                    BatchOp::Remove(key) => {
+                        table.remove(key.as_str())?;
+                    }
+                    BatchOp::Clear => table.retain(|_, _| false)?,
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize, GetError> {
+        let read_txn = self.begin_read()?;
+        let table = match read_txn.open_table(TABLE) {
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+            table => table?,
+        };
+        Ok(table.len()? as usize)
+    }
+
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        let read_txn = match self.begin_read() {
+            Ok(read_txn) => read_txn,
+            Err(err) => return Box::new(std::iter::once(Err(err.into()))),
+        };
+        let table = match read_txn.open_table(TABLE) {
+            Ok(table) => table,
+            // the table is deleted by `clear`
+            Err(redb::TableError::TableDoesNotExist(_)) => return Box::new(std::iter::empty()),
+            Err(err) => return Box::new(std::iter::once(Err(err.into()))),
+        };
+        match table.range::<&str>(range) {
+            Ok(range) => Box::new(range.map(|entry| {
+                let (key, value) = entry?;
+                Ok((key.value().to_string(), value.value().to_vec()))
+            })),
+            Err(err) => Box::new(std::iter::once(Err(err.into()))),
+        }
+    }
 }
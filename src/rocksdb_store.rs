@@ -1,108 +1,181 @@
-use crate::{Location, StoreImpl};
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::error::impl_from_backend_errors;
+use crate::{
+    BackendEntries, BatchOp, CasError, ErrorKind, GetError, Location, OpenError, PkvBackend,
+    RemoveError, SetError,
+};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A [`PkvBackend`] storing the data in a [RocksDB](https://docs.rs/rocksdb) database
 #[derive(Debug)]
 pub struct RocksDBStore {
     db: rocksdb::DB,
+    /// Held by all writes, so read-modify-write operations, which RocksDB has no
+    /// primitive for, can't be interleaved with other writes
+    write_lock: Mutex<()>,
 }
 
-pub use RocksDBStore as InnerStore;
-
-/// Errors that can occur during `PkvStore::get`
-#[derive(thiserror::Error, Debug)]
-pub enum GetError {
-    /// An internal error from the rocksdb crate
-    #[error("Rocksdb error")]
-    Rocksdb(#[from] rocksdb::Error),
-    /// Error when deserializing the value
-    #[error("MessagePack deserialization error")]
-    MessagePack(#[from] rmp_serde::decode::Error),
-    /// The value for the given key was not found
-    #[error("No value found for the given key")]
-    NotFound,
-}
-/// Errors that can occur during `PkvStore::set`
-#[derive(thiserror::Error, Debug)]
-pub enum SetError {
-    /// An internal error from the rocksdb crate
-    #[error("Rocksdb error")]
-    Rocksdb(#[from] rocksdb::Error),
-    /// Error when serializing the value
-    #[error("MessagePack serialization error")]
-    MessagePack(#[from] rmp_serde::encode::Error),
+fn classify(err: rocksdb::Error) -> (ErrorKind, Box<dyn std::error::Error + Send + Sync>) {
+    let kind = match err.kind() {
+        rocksdb::ErrorKind::IOError => ErrorKind::Io,
+        rocksdb::ErrorKind::Corruption => ErrorKind::Corruption,
+        _ => ErrorKind::Backend,
+    };
+    (kind, err.into())
 }
 
-/// Errors that can occur during `PkvStore::remove`
-#[derive(thiserror::Error, Debug)]
-pub enum RemoveError {
-    /// An internal error from the rocksdb crate
-    #[error("Rocksdb error")]
-    Rocksdb(#[from] rocksdb::Error),
-    /// Error when deserializing the value
-    #[error("MessagePack deserialization error")]
-    MessagePack(#[from] rmp_serde::decode::Error),
-    /// The value for the given key was not found
-    #[error("No value found for the given key")]
-    NotFound,
-}
+impl_from_backend_errors!(classify: rocksdb::Error);
 
 impl RocksDBStore {
-    pub(crate) fn new(location: Location) -> Self {
-        let mut options = rocksdb::Options::default();
-        options.set_error_if_exists(false);
-        options.create_if_missing(true);
-        options.create_missing_column_families(true);
+    /// Opens or creates a database named `bevy_rocksdb_pkv` in the given directory
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        Self::new(Location::CustomPath(path.as_ref()))
+    }
 
+    pub(crate) fn new(location: Location) -> Result<Self, OpenError> {
+        Self::new_with_filename(location, "bevy_rocksdb_pkv")
+    }
+
+    /// Opens an existing database without creating or writing anything
+    #[cfg_attr(any(redb_backend, sled_backend), allow(dead_code))] // only used when rocksdb is the default backend
+    pub(crate) fn new_read_only(location: Location) -> Result<Self, OpenError> {
         let db_path = location.get_path().join("bevy_rocksdb_pkv");
-        let db = rocksdb::DB::open(&options, db_path).expect("Failed to init key value store");
-        Self { db }
+        std::fs::metadata(&db_path)?;
+        let db = rocksdb::DB::open_for_read_only(&rocksdb::Options::default(), &db_path, false)
+            .map_err(|err| open_error(err, &db_path))?;
+        Ok(Self {
+            db,
+            write_lock: Mutex::new(()),
+        })
     }
 
-    pub(crate) fn new_with_filename(location: Location, filename: &str) -> Self {
+    pub(crate) fn new_with_filename(location: Location, filename: &str) -> Result<Self, OpenError> {
         let mut options = rocksdb::Options::default();
         options.set_error_if_exists(false);
         options.create_if_missing(true);
         options.create_missing_column_families(true);
 
         let db_path = location.get_path().join(filename);
-        let db = rocksdb::DB::open(&options, db_path).expect("Failed to init key value store");
-        Self { db }
+        let db = rocksdb::DB::open(&options, &db_path).map_err(|err| open_error(err, &db_path))?;
+        Ok(Self {
+            db,
+            write_lock: Mutex::new(()),
+        })
     }
 }
 
-impl StoreImpl for RocksDBStore {
-    type GetError = GetError;
-    type SetError = SetError;
-    type RemoveError = RemoveError;
+/// RocksDB locks the `LOCK` file in the database directory
+fn open_error(err: rocksdb::Error, db_path: &Path) -> OpenError {
+    match err.kind() {
+        // "IO error: While lock file: <path>/LOCK: Resource temporarily unavailable" if
+        // another process has it open, or "IO error: lock hold by current process,
+        // acquire time ..." if this one does
+        rocksdb::ErrorKind::IOError if is_lock_error(err.as_ref()) => OpenError::Locked {
+            path: db_path.join("LOCK"),
+            source: err.into(),
+        },
+        rocksdb::ErrorKind::IOError => OpenError::Io(std::io::Error::other(err)),
+        rocksdb::ErrorKind::Corruption => OpenError::Corruption(err.into()),
+        _ => OpenError::Backend(err.into()),
+    }
+}
 
-    /// Serialize and store the value
-    fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), Self::SetError> {
-        let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
-        value.serialize(&mut serializer)?;
-        self.db.put(key, serializer.into_inner())?;
+fn is_lock_error(message: &str) -> bool {
+    message.contains("While lock file") || message.contains("lock hold by current process")
+}
 
-        Ok(())
+impl RocksDBStore {
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Iterates in key order from `start`, for as long as `in_range` returns true
+    fn entries<'a>(
+        &'a self,
+        start: Bound<&str>,
+        mut in_range: impl FnMut(&str) -> bool + 'a,
+    ) -> BackendEntries<'a> {
+        let mode = match start {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                rocksdb::IteratorMode::From(key.as_bytes(), rocksdb::Direction::Forward)
+            }
+            Bound::Unbounded => rocksdb::IteratorMode::Start,
+        };
+        let excluded = match start {
+            Bound::Excluded(key) => Some(key.to_string()),
+            _ => None,
+        };
+        Box::new(
+            self.db
+                .iterator(mode)
+                .map(|kv| {
+                    let (key, bytes) = kv?;
+                    Ok((String::from_utf8_lossy(&key).into_owned(), bytes.into_vec()))
+                })
+                .skip_while(move |entry| match (entry, &excluded) {
+                    (Ok((key, _)), Some(excluded)) => key == excluded,
+                    _ => false,
+                })
+                .take_while(move |entry| match entry {
+                    Ok((key, _)) => in_range(key),
+                    Err(_) => true,
+                }),
+        )
     }
+}
 
-    /// More or less the same as set::<String>, but can take a &str
-    fn set_string(&mut self, key: &str, value: &str) -> Result<(), Self::SetError> {
-        let bytes = rmp_serde::to_vec(value)?;
-        self.db.put(key, bytes)?;
+impl PkvBackend for RocksDBStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        Ok(self.db.get(key)?)
+    }
 
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError> {
+        let _guard = self.lock();
+        self.db.put(key, value)?;
         Ok(())
     }
 
-    /// Get the value for the given key
-    /// returns Err(GetError::NotFound) if the key does not exist in the key value store.
-    fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, Self::GetError> {
-        let bytes = self.db.get(key)?.ok_or(Self::GetError::NotFound)?;
-        let value = rmp_serde::from_slice(&bytes)?;
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        let _guard = self.lock();
+        let value = self.db.get(key)?;
+        if value.is_some() {
+            self.db.delete(key)?;
+        }
         Ok(value)
     }
 
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        Ok(self.db.get_pinned(key)?.is_some())
+    }
+
+    /// RocksDB only allows a single process to open the database, and all writes
+    /// hold the write lock, so reading and then writing is atomic
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        let _guard = self.lock();
+        let current = self.db.get(key).map_err(GetError::from)?;
+        if current.as_deref() != expected {
+            return Err(CasError::Mismatch { current });
+        }
+        match new {
+            Some(bytes) => self.db.put(key, bytes),
+            None => self.db.delete(key),
+        }
+        .map_err(SetError::from)?;
+        Ok(())
+    }
+
     /// Clear all keys and their values
     /// The RocksDB adapter uses an iterator to achieve this, unlike sled
-    fn clear(&mut self) -> Result<(), Self::SetError> {
+    fn clear(&self) -> Result<(), SetError> {
+        let _guard = self.lock();
         let kv_iter = self.db.iterator(rocksdb::IteratorMode::Start);
 
         for kv in kv_iter {
@@ -113,18 +186,62 @@ impl StoreImpl for RocksDBStore {
         Ok(())
     }
 
-    fn remove(&mut self, key: &str) -> Result<(), Self::RemoveError> {
-        self.db.delete(key)?;
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
+        let _guard = self.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+        let mode = rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward);
+        for kv in self.db.iterator(mode) {
+            let (key, _) = kv?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            batch.delete(key);
+        }
+        self.db.write(batch)?;
         Ok(())
     }
 
-    fn remove_and_get<T: DeserializeOwned>(
-        &mut self,
-        key: &str,
-    ) -> Result<Option<T>, Self::RemoveError> {
-        let bytes = self.db.get(key)?.ok_or(Self::RemoveError::NotFound)?;
-        let value = rmp_serde::from_slice(&bytes)?;
-        self.db.delete(key)?;
-        Ok(value)
+    /// Applies all operations as a single `rocksdb::WriteBatch`
+    fn write_batch(&self, ops: Vec<BatchOp>) -> Result<(), SetError> {
+        let _guard = self.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+        for op in ops {
+            match op {
+                BatchOp::Set(key, bytes) => batch.put(key, bytes),
+                BatchOp::Remove(key) => batch.delete(key),
+                BatchOp::Clear => {
+                    // earlier operations in the batch are cleared as well
+                    batch.clear();
+                    for kv in self.db.iterator(rocksdb::IteratorMode::Start) {
+                        let (key, _) = kv?;
+                        batch.delete(key);
+                    }
+                }
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = Result<String, GetError>> + '_> {
+        Box::new(self.db.iterator(rocksdb::IteratorMode::Start).map(|kv| {
+            let (key, _) = kv?;
+            Ok(String::from_utf8_lossy(&key).into_owned())
+        }))
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> BackendEntries<'_> {
+        let start = Bound::Included(prefix);
+        let prefix = prefix.to_string();
+        self.entries(start, move |key| key.starts_with(&prefix))
+    }
+
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        let end = range.1.map(str::to_string);
+        self.entries(range.0, move |key| match &end {
+            Bound::Included(end) => key <= end.as_str(),
+            Bound::Excluded(end) => key < end.as_str(),
+            Bound::Unbounded => true,
+        })
     }
 }
@@ -1,115 +1,230 @@
-use crate::{Location, StoreImpl};
-use serde::{de::DeserializeOwned, Serialize};
+use crate::error::impl_from_backend_errors;
+use crate::{
+    BackendEntries, BatchOp, CasError, ChangeKind, Changed, ErrorKind, GetError, Location,
+    OpenError, PkvBackend, RemoveError, SetError,
+};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::Duration;
 
+/// How often the threads forwarding watched changes check whether the store was dropped
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A [`PkvBackend`] storing the data in a [sled](https://docs.rs/sled) database
 #[derive(Debug)]
 pub struct SledStore {
     db: sled::Db,
+    /// Set when the store is dropped, to stop the threads forwarding watched changes
+    dropped: Arc<AtomicBool>,
 }
 
-pub use SledStore as InnerStore;
-
-/// Errors that can occur during `PkvStore::get`
-#[derive(thiserror::Error, Debug)]
-pub enum GetError {
-    /// An internal error from the sled crate
-    #[error("Sled error")]
-    Sled(#[from] sled::Error),
-    /// Error when deserializing the value
-    #[error("MessagePack deserialization error")]
-    MessagePack(#[from] rmp_serde::decode::Error),
-    /// The value for the given key was not found
-    #[error("No value found for the given key")]
-    NotFound,
+impl Drop for SledStore {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::Relaxed);
+    }
 }
 
-/// Errors that can occur during `PkvStore::set`
-#[derive(thiserror::Error, Debug)]
-pub enum SetError {
-    /// An internal error from the sled crate
-    #[error("Sled error")]
-    Sled(#[from] sled::Error),
-    /// Error when serializing the value
-    #[error("MessagePack serialization error")]
-    MessagePack(#[from] rmp_serde::encode::Error),
+fn classify(err: sled::Error) -> (ErrorKind, Box<dyn std::error::Error + Send + Sync>) {
+    let kind = match err {
+        sled::Error::Io(_) => ErrorKind::Io,
+        sled::Error::Corruption { .. } => ErrorKind::Corruption,
+        _ => ErrorKind::Backend,
+    };
+    (kind, err.into())
 }
 
-/// Errors that can occur during `PkvStore::remove`
-#[derive(thiserror::Error, Debug)]
-pub enum RemoveError {
-    /// An internal error from the sled crate
-    #[error("Sled error")]
-    Sled(#[from] sled::Error),
-    /// Error when deserializing the value
-    #[error("MessagePack deserialization error")]
-    MessagePack(#[from] rmp_serde::decode::Error),
-    /// The value for the given key was not found
-    #[error("No value found for the given key")]
-    NotFound,
-}
+impl_from_backend_errors!(classify: sled::Error);
 
 impl SledStore {
-    pub(crate) fn new(location: Location) -> Self {
-        let db_path = location.get_path().join("bevy_pkv.sled");
-        let db = sled::open(db_path).expect("Failed to init key value store");
-        Self { db }
+    /// Opens or creates a database named `bevy_pkv.sled` in the given directory
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, OpenError> {
+        Self::new(Location::CustomPath(path.as_ref()))
+    }
+
+    pub(crate) fn new(location: Location) -> Result<Self, OpenError> {
+        Self::new_with_filename(location, "bevy_pkv.sled")
     }
 
-    pub(crate) fn new_with_filename(location: Location, filename: &str) -> Self {
+    /// sled has no read-only mode, so this only makes sure no new database is created.
+    /// Writes are rejected by `PkvStore`.
+    #[cfg_attr(redb_backend, allow(dead_code))] // only used when sled is the default backend
+    pub(crate) fn new_read_only(location: Location) -> Result<Self, OpenError> {
+        std::fs::metadata(location.get_path().join("bevy_pkv.sled"))?;
+        Self::new(location)
+    }
+
+    pub(crate) fn new_with_filename(location: Location, filename: &str) -> Result<Self, OpenError> {
         let db_path = location.get_path().join(filename);
-        let db = sled::open(db_path).expect("Failed to init key value store");
-        Self { db }
+        let db = sled::open(&db_path).map_err(|err| open_error(err, &db_path))?;
+        Ok(Self {
+            db,
+            dropped: Arc::default(),
+        })
     }
 }
 
-impl StoreImpl for SledStore {
-    type GetError = GetError;
-    type SetError = SetError;
-    type RemoveError = RemoveError;
+/// sled locks the `db` file in the database directory
+fn open_error(err: sled::Error, db_path: &Path) -> OpenError {
+    match err {
+        // sled reports failing to lock the database as an `Other` io error
+        sled::Error::Io(err) if err.to_string().starts_with("could not acquire lock") => {
+            OpenError::Locked {
+                path: db_path.join("db"),
+                source: err.into(),
+            }
+        }
+        sled::Error::Io(err) => OpenError::Io(err),
+        err @ sled::Error::Corruption { .. } => OpenError::Corruption(err.into()),
+        err => OpenError::Backend(err.into()),
+    }
+}
 
-    /// Serialize and store the value
-    fn set<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), Self::SetError> {
-        let mut serializer = rmp_serde::Serializer::new(Vec::new()).with_struct_map();
-        value.serialize(&mut serializer)?;
-        self.db.insert(key, serializer.into_inner())?;
-        self.db.flush()?;
-        Ok(())
+fn entries<'a>(iter: sled::Iter) -> BackendEntries<'a> {
+    Box::new(iter.map(|entry| {
+        let (key, bytes) = entry?;
+        Ok((String::from_utf8_lossy(&key).into_owned(), bytes.to_vec()))
+    }))
+}
+
+impl PkvBackend for SledStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GetError> {
+        Ok(self.db.get(key)?.map(|bytes| bytes.to_vec()))
     }
 
-    /// More or less the same as set::<String>, but can take a &str
-    fn set_string(&mut self, key: &str, value: &str) -> Result<(), Self::SetError> {
-        let bytes = rmp_serde::to_vec(value)?;
-        self.db.insert(key, bytes)?;
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), SetError> {
+        self.db.insert(key, value)?;
         self.db.flush()?;
         Ok(())
     }
 
-    /// Get the value for the given key
-    /// returns Err(GetError::NotFound) if the key does not exist in the key value store.
-    fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, Self::GetError> {
-        let bytes = self.db.get(key)?.ok_or(Self::GetError::NotFound)?;
-        let value = rmp_serde::from_slice(&bytes)?;
-        Ok(value)
+    fn remove(&self, key: &str) -> Result<Option<Vec<u8>>, RemoveError> {
+        let value = self.db.remove(key)?;
+        Ok(value.map(|bytes| bytes.to_vec()))
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool, GetError> {
+        Ok(self.db.contains_key(key)?)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), CasError<Vec<u8>>> {
+        let swapped = self
+            .db
+            .compare_and_swap(key, expected, new)
+            .map_err(SetError::from)?;
+        if let Err(err) = swapped {
+            let current = err.current.map(|bytes| bytes.to_vec());
+            return Err(CasError::Mismatch { current });
+        }
+        self.db.flush().map_err(SetError::from)?;
+        Ok(())
     }
 
     /// Clear all keys and their values
     /// clear is also a kind of store so it will return SetError on failure
-    fn clear(&mut self) -> Result<(), Self::SetError> {
+    fn clear(&self) -> Result<(), SetError> {
         self.db.clear()?;
         self.db.flush()?;
         Ok(())
     }
 
-    fn remove(&mut self, key: &str) -> Result<(), Self::RemoveError> {
-        self.db.remove(key)?;
+    fn clear_prefix(&self, prefix: &str) -> Result<(), SetError> {
+        let mut batch = sled::Batch::default();
+        for key in self.db.scan_prefix(prefix).keys() {
+            batch.remove(key?);
+        }
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
         Ok(())
     }
 
-    fn remove_and_get<T: DeserializeOwned>(
-        &mut self,
-        key: &str,
-    ) -> Result<Option<T>, Self::RemoveError> {
-        let bytes = self.db.remove(key)?.ok_or(Self::RemoveError::NotFound)?;
-        let value = rmp_serde::from_slice(&bytes)?;
-        Ok(value)
+    /// Applies all operations as a single `sled::Batch`
+    fn write_batch(&self, ops: Vec<BatchOp>) -> Result<(), SetError> {
+        // sled batches only hold the final write for each key, and can't express clear,
+        // so resolve the operations up front, turning clear into removal of every key
+        let mut writes = BTreeMap::new();
+        for op in ops {
+            match op {
+                BatchOp::Set(key, bytes) => {
+                    writes.insert(key.into_bytes(), Some(bytes));
+                }
+                BatchOp::Remove(key) => {
+                    writes.insert(key.into_bytes(), None);
+                }
+                BatchOp::Clear => {
+                    writes.values_mut().for_each(|value| *value = None);
+                    for key in self.db.iter().keys() {
+                        writes.insert(key?.to_vec(), None);
+                    }
+                }
+            }
+        }
+        let mut batch = sled::Batch::default();
+        for (key, value) in writes {
+            match value {
+                Some(bytes) => batch.insert(key, bytes),
+                None => batch.remove(key),
+            }
+        }
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Forwards the events of sled's `watch_prefix` from a background thread, which
+    /// stops at the first event after the receiver is dropped
+    fn watch_prefix(&self, prefix: &str, sender: Sender<Changed>) -> bool {
+        let mut subscriber = self.db.watch_prefix(prefix);
+        let dropped = self.dropped.clone();
+        // a dropped receiver is only noticed on the next change, so the thread also
+        // stops when the store is dropped
+        let forward = move || {
+            while !dropped.load(Ordering::Relaxed) {
+                let event = match subscriber.next_timeout(WATCH_POLL_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                let (key, kind) = match event {
+                    sled::Event::Insert { key, .. } => (key, ChangeKind::Set),
+                    sled::Event::Remove { key } => (key, ChangeKind::Removed),
+                };
+                let key = String::from_utf8_lossy(&key).into_owned();
+                if sender.send(Changed { key, kind }).is_err() {
+                    break;
+                }
+            }
+        };
+        std::thread::Builder::new()
+            .name("bevy_pkv sled watcher".to_string())
+            .spawn(forward)
+            .is_ok()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = Result<String, GetError>> + '_> {
+        Box::new(self.db.iter().keys().map(|key| {
+            let key = key?;
+            Ok(String::from_utf8_lossy(&key).into_owned())
+        }))
+    }
+
+    fn len(&self) -> Result<usize, GetError> {
+        Ok(self.db.len())
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> BackendEntries<'_> {
+        entries(self.db.scan_prefix(prefix))
+    }
+
+    fn range(&self, range: (Bound<&str>, Bound<&str>)) -> BackendEntries<'_> {
+        entries(self.db.range::<&str, _>(range))
     }
 }
@@ -0,0 +1,78 @@
+//! Subscriptions to changes of keys
+
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A change to a key in a [`PkvStore`](crate::PkvStore), as received from
+/// [`PkvStore::watch`](crate::PkvStore::watch)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changed {
+    /// The key that changed, or for [`ChangeKind::Cleared`], the prefix of the keys
+    /// that were cleared, which is empty if the whole store was cleared
+    pub key: String,
+    /// What happened to the key
+    pub kind: ChangeKind,
+}
+
+/// The kind of a [`Changed`] event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// A value was stored for the key
+    Set,
+    /// The value for the key was removed
+    Removed,
+    /// All keys starting with the prefix were removed
+    Cleared,
+}
+
+#[derive(Debug)]
+struct Watcher {
+    prefix: String,
+    sender: Sender<Changed>,
+    /// Sets and removals are reported by the backend itself
+    native: bool,
+}
+
+/// The watchers of a store, which are notified of the changes made through it
+///
+/// Shared with the I/O thread when the `async` feature is enabled.
+#[derive(Debug, Default)]
+pub(crate) struct Watchers(Mutex<Vec<Watcher>>);
+
+impl Watchers {
+    fn watchers(&self) -> MutexGuard<'_, Vec<Watcher>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn add(&self, prefix: &str, sender: Sender<Changed>, native: bool) {
+        self.watchers().push(Watcher {
+            prefix: prefix.to_string(),
+            sender,
+            native,
+        });
+    }
+
+    pub(crate) fn notify(&self, key: &str, kind: ChangeKind) {
+        let mut watchers = self.watchers();
+        if watchers.is_empty() {
+            return;
+        }
+        watchers.retain(|watcher| {
+            let affected = match kind {
+                ChangeKind::Cleared => {
+                    watcher.prefix.starts_with(key) || key.starts_with(&watcher.prefix)
+                }
+                _ => !watcher.native && key.starts_with(&watcher.prefix),
+            };
+            if !affected {
+                return true;
+            }
+            let changed = Changed {
+                key: key.to_string(),
+                kind,
+            };
+            // stop watching once the receiver is dropped
+            watcher.sender.send(changed).is_ok()
+        });
+    }
+}